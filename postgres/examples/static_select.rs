@@ -28,7 +28,7 @@ fn main() {
     tokio::run(lazy(|| {
         Pool::builder()
             .build(pg_mgr)
-            .map_err(|e| bb8::RunError::User(e))
+            .map_err(bb8::RunError::User)
             .and_then(|pool| {
                 pool.run(|mut connection| {
                     connection.prepare("SELECT 1").then(move |r| match r {
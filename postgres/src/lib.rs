@@ -2,19 +2,51 @@
 #![deny(missing_docs, missing_debug_implementations)]
 
 pub extern crate bb8;
+#[cfg(feature = "native-tls")]
+pub extern crate native_tls;
+#[cfg(feature = "rustls")]
+pub extern crate rustls;
 pub extern crate tokio_postgres;
 
 extern crate futures;
+#[cfg(feature = "native-tls")]
+extern crate tokio_postgres_native_tls;
+#[cfg(feature = "rustls")]
+extern crate tokio_postgres_rustls;
 
 use futures::prelude::*;
+use futures::stream;
 use tokio_postgres::config::Config;
 use tokio_postgres::tls::{MakeTlsConnect, TlsConnect};
 use tokio_postgres::{Client, Error, Socket};
+#[cfg(feature = "native-tls")]
+pub use tokio_postgres_native_tls::MakeTlsConnector;
+#[cfg(feature = "rustls")]
+use tokio_postgres_rustls::MakeRustlsConnect;
 
 use std::fmt;
 use std::str::FromStr;
+#[cfg(feature = "rustls")]
+use std::sync::{Arc, Mutex};
+
+mod multiplexed;
+
+pub use multiplexed::{MultiplexedConnectionManager, SharedClient};
 
 /// A `bb8::ManageConnection` for `tokio_postgres::Connection`s.
+///
+/// # Pipelining
+///
+/// A `Client` pipelines the queries made through it: each query is sent to
+/// the server as soon as its future is created, without waiting for earlier
+/// ones to complete. So one checkout can keep many queries in flight on a
+/// single connection, e.g. by creating their futures up front and joining
+/// them, as the `pipeline` example does. This is the way to get the most out
+/// of each connection for pipelined workloads.
+///
+/// Each connection is still checked out by one user at a time, as the
+/// `Client`'s methods take `&mut self`. To share connections between
+/// concurrent checkouts instead, opt in with `multiplexed`.
 #[derive(Clone)]
 pub struct PostgresConnectionManager<Tls>
 where
@@ -22,6 +54,8 @@ where
 {
     config: Config,
     tls: Tls,
+    validation_query: Option<String>,
+    setup: Vec<String>,
 }
 
 impl<Tls> PostgresConnectionManager<Tls>
@@ -29,10 +63,17 @@ where
     Tls: MakeTlsConnect<Socket>,
 {
     /// Create a new `PostgresConnectionManager` with the specified `config`.
+    ///
+    /// `tls` is any `MakeTlsConnect` implementation, such as
+    /// `tokio_postgres::NoTls` for unencrypted connections. With the
+    /// `native-tls` or `rustls` feature, `new_native_tls` and `new_rustls`
+    /// create a manager that connects over TLS.
     pub fn new(config: Config, tls: Tls) -> PostgresConnectionManager<Tls> {
         PostgresConnectionManager {
-            config: config,
-            tls: tls,
+            config,
+            tls,
+            validation_query: None,
+            setup: Vec::new(),
         }
     }
 
@@ -48,6 +89,120 @@ where
         let config = Config::from_str(&stringified_params)?;
         Ok(Self::new(config, tls))
     }
+
+    /// Sets the SQL run by `is_valid` to check that a connection is healthy.
+    ///
+    /// The query is sent with the simple query protocol, so it is not
+    /// prepared on the server, and any rows it returns are ignored. An empty
+    /// query disables validation entirely, even if the pool is configured to
+    /// `test_on_check_out`.
+    ///
+    /// By default an empty query string is sent to the server to check that
+    /// the connection is responsive.
+    pub fn with_validation_query<T>(mut self, query: T) -> PostgresConnectionManager<Tls>
+    where
+        T: ToString,
+    {
+        self.validation_query = Some(query.to_string());
+        self
+    }
+
+    /// Sets statements to run on every new connection, e.g. `SET` commands to
+    /// configure the session.
+    ///
+    /// The statements are run in order, with the simple query protocol, before
+    /// the connection is added to the pool. If any of them fails the
+    /// connection attempt fails too.
+    pub fn with_setup(mut self, statements: Vec<String>) -> PostgresConnectionManager<Tls> {
+        self.setup = statements;
+        self
+    }
+
+    /// Turns this into a manager whose connections are each shared by up to
+    /// `max_checkouts` checkouts at once, pipelining their requests together.
+    ///
+    /// See `MultiplexedConnectionManager` for what sharing a connection
+    /// entails. The setup statements are run once per connection, not per
+    /// checkout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_checkouts` is 0.
+    pub fn multiplexed(self, max_checkouts: usize) -> MultiplexedConnectionManager<Tls> {
+        MultiplexedConnectionManager::new(self, max_checkouts)
+    }
+
+    // Sends the validation query over `client`, if there is one.
+    fn validate(&self, client: &mut Client) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+        let query = match self.validation_query {
+            Some(ref query) if query.is_empty() => return Box::new(futures::future::ok(())),
+            Some(ref query) => query.as_str(),
+            None => "",
+        };
+        Box::new(client.simple_query(query).collect().map(|_| ()))
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl PostgresConnectionManager<MakeTlsConnector> {
+    /// Create a new `PostgresConnectionManager` with the specified `config`,
+    /// connecting over TLS with the given `native_tls` connector.
+    ///
+    /// ```ignore
+    /// let connector = native_tls::TlsConnector::builder().build()?;
+    /// let manager = PostgresConnectionManager::new_native_tls(config, connector);
+    /// ```
+    pub fn new_native_tls(
+        config: Config,
+        connector: native_tls::TlsConnector,
+    ) -> PostgresConnectionManager<MakeTlsConnector> {
+        Self::new(config, MakeTlsConnector::new(connector))
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl PostgresConnectionManager<RustlsConnector> {
+    /// Create a new `PostgresConnectionManager` with the specified `config`,
+    /// connecting over TLS with `rustls` configured by `tls_config`.
+    ///
+    /// `tls_config` must trust the server's certificate, e.g. by adding the
+    /// roots from the `webpki-roots` crate to its `root_store`.
+    pub fn new_rustls(
+        config: Config,
+        tls_config: rustls::ClientConfig,
+    ) -> PostgresConnectionManager<RustlsConnector> {
+        let tls = RustlsConnector(Arc::new(Mutex::new(MakeRustlsConnect::new(tls_config))));
+        Self::new(config, tls)
+    }
+}
+
+/// The `MakeTlsConnect` used by `PostgresConnectionManager::new_rustls`.
+///
+/// This wraps a `tokio_postgres_rustls::MakeRustlsConnect`, which the manager
+/// can't use directly because it isn't `Clone`.
+#[cfg(feature = "rustls")]
+#[derive(Clone)]
+pub struct RustlsConnector(Arc<Mutex<MakeRustlsConnect>>);
+
+#[cfg(feature = "rustls")]
+impl fmt::Debug for RustlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RustlsConnector").finish()
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl<S> MakeTlsConnect<S> for RustlsConnector
+where
+    MakeRustlsConnect: MakeTlsConnect<S>,
+{
+    type Stream = <MakeRustlsConnect as MakeTlsConnect<S>>::Stream;
+    type TlsConnect = <MakeRustlsConnect as MakeTlsConnect<S>>::TlsConnect;
+    type Error = <MakeRustlsConnect as MakeTlsConnect<S>>::Error;
+
+    fn make_tls_connect(&mut self, domain: &str) -> Result<Self::TlsConnect, Self::Error> {
+        self.0.lock().unwrap().make_tls_connect(domain)
+    }
 }
 
 impl<Tls> bb8::ManageConnection for PostgresConnectionManager<Tls>
@@ -62,16 +217,27 @@ where
 
     fn connect(
         &self,
-    ) -> Box<Future<Item = Self::Connection, Error = Self::Error> + Send + 'static> {
+    ) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send + 'static> {
+        let setup = self.setup.clone();
         Box::new(
             self.config
                 .connect(self.tls.clone())
                 .map(|(client, connection)| {
                     // The connection object performs the actual communication with the database,
-                    // so spawn it off to run on its own.
-                    tokio::spawn(connection.map_err(|_| panic!()));
+                    // so spawn it off to run on its own. It completes when the socket is closed,
+                    // with an error if that happened unexpectedly, at which point the client
+                    // reports itself as closed and `has_broken` will discard it. The client
+                    // tracks this through the channel to the task, so the manager doesn't need
+                    // a handle to the task itself. There's nobody to hand the error to, so it's
+                    // dropped rather than taking down the executor.
+                    tokio::spawn(connection.map_err(|_| ()));
 
                     client
+                })
+                .and_then(move |client| {
+                    stream::iter_ok(setup).fold(client, |mut client, statement| {
+                        client.simple_query(&statement).collect().map(|_| client)
+                    })
                 }),
         )
     }
@@ -79,8 +245,9 @@ where
     fn is_valid(
         &self,
         mut conn: Self::Connection,
-    ) -> Box<Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send> {
-        let f = conn.simple_query("").collect();
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        let f = self.validate(&mut conn);
         Box::new(f.then(move |r| match r {
             Ok(_) => Ok(conn),
             Err(e) => Err((e, conn)),
@@ -88,6 +255,7 @@ where
     }
 
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        // True once the connection task spawned in `connect` has finished.
         conn.is_closed()
     }
 }
@@ -99,6 +267,8 @@ where
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("PostgresConnectionManager")
             .field("config", &self.config)
+            .field("validation_query", &self.validation_query)
+            .field("setup", &self.setup)
             .finish()
     }
 }
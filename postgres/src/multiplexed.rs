@@ -0,0 +1,151 @@
+//! A manager whose connections are shared by several checkouts at once.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use futures::future::ok;
+use futures::prelude::*;
+use tokio_postgres::tls::{MakeTlsConnect, TlsConnect};
+use tokio_postgres::{Client, Error, Socket};
+
+use PostgresConnectionManager;
+
+/// A `bb8::ManageConnection` whose connections are each shared by up to a
+/// fixed number of checkouts at once, created with
+/// `PostgresConnectionManager::multiplexed`.
+///
+/// The pool manages `SharedClient` handles rather than connections. Opening a
+/// handle reuses an open connection that has fewer than `max_checkouts`
+/// handles, and only opens a new connection once every open one is fully
+/// shared. A connection stays open as long as any of its handles does, idle
+/// or checked out, so the pool's `max_size` bounds the number of handles and
+/// e.g. a `max_size` of 20 with 4 checkouts per connection needs about 5
+/// connections. Connections being opened at the same time can't be shared
+/// yet, so a burst of checkouts may open a few more. Handles of a connection
+/// that has closed are all broken.
+///
+/// Requests from the checkouts sharing a connection are pipelined together,
+/// which raises throughput for workloads of many short, independent queries.
+/// In exchange, the checkouts share the connection's session: a transaction,
+/// `SET` command or temporary table started by one checkout is seen by every
+/// other one, and so is an error that aborts a transaction. Only use this for
+/// statements that stand on their own.
+pub struct MultiplexedConnectionManager<Tls>
+where
+    Tls: MakeTlsConnect<Socket>,
+{
+    manager: PostgresConnectionManager<Tls>,
+    max_checkouts: usize,
+    // The open connections. Each one is closed once its last handle is
+    // dropped.
+    clients: Arc<Mutex<Vec<Weak<Mutex<Client>>>>>,
+}
+
+impl<Tls> MultiplexedConnectionManager<Tls>
+where
+    Tls: MakeTlsConnect<Socket>,
+{
+    pub(crate) fn new(
+        manager: PostgresConnectionManager<Tls>,
+        max_checkouts: usize,
+    ) -> MultiplexedConnectionManager<Tls> {
+        assert!(
+            max_checkouts > 0,
+            "max_checkouts must be greater than zero!"
+        );
+        MultiplexedConnectionManager {
+            manager,
+            max_checkouts,
+            clients: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns the number of checkouts each connection is shared by at most.
+    pub fn max_checkouts(&self) -> usize {
+        self.max_checkouts
+    }
+
+    // Returns a new handle to an open connection with room for one, if any.
+    fn share(&self) -> Option<SharedClient> {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|client| client.strong_count() > 0);
+        clients
+            .iter()
+            .filter_map(Weak::upgrade)
+            // Upgrading counts as a handle too.
+            .find(|client| {
+                Arc::strong_count(client) <= self.max_checkouts
+                    && !client.lock().unwrap().is_closed()
+            })
+            .map(|client| SharedClient { client })
+    }
+}
+
+impl<Tls> fmt::Debug for MultiplexedConnectionManager<Tls>
+where
+    Tls: MakeTlsConnect<Socket>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultiplexedConnectionManager")
+            .field("manager", &self.manager)
+            .field("max_checkouts", &self.max_checkouts)
+            .finish()
+    }
+}
+
+impl<Tls> bb8::ManageConnection for MultiplexedConnectionManager<Tls>
+where
+    Tls: MakeTlsConnect<Socket> + Clone + Send + Sync + 'static,
+    <Tls as MakeTlsConnect<Socket>>::Stream: Send + Sync,
+    <Tls as MakeTlsConnect<Socket>>::TlsConnect: Send,
+    <<Tls as MakeTlsConnect<Socket>>::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    type Connection = SharedClient;
+    type Error = Error;
+
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+        if let Some(client) = self.share() {
+            return Box::new(ok(client));
+        }
+        let clients = self.clients.clone();
+        Box::new(self.manager.connect().map(move |client| {
+            let client = Arc::new(Mutex::new(client));
+            clients.lock().unwrap().push(Arc::downgrade(&client));
+            SharedClient { client }
+        }))
+    }
+
+    fn is_valid_mut(
+        &self,
+        conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = (), Error = Self::Error> + Send> {
+        self.manager.validate(&mut conn.lock())
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.lock().is_closed()
+    }
+}
+
+/// A handle to a connection shared by several checkouts, opened by a
+/// `MultiplexedConnectionManager`.
+pub struct SharedClient {
+    client: Arc<Mutex<Client>>,
+}
+
+impl SharedClient {
+    /// Locks the shared `Client` to make requests with it.
+    ///
+    /// The futures the `Client` returns don't borrow it, so only hold the lock
+    /// while creating them and release it before waiting on them. This lets
+    /// the requests of other checkouts be pipelined alongside.
+    pub fn lock(&self) -> MutexGuard<'_, Client> {
+        self.client.lock().unwrap()
+    }
+}
+
+impl fmt::Debug for SharedClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedClient").finish()
+    }
+}
@@ -0,0 +1,31 @@
+//! A blocking version of the checkout API, for synchronous code. Enabled by
+//! the `blocking` feature.
+
+use tokio::runtime::current_thread::Runtime;
+
+use {ManageConnection, Pool, PooledConnection, RunError};
+
+impl<M: ManageConnection> Pool<M> {
+    /// Retrieves a connection from the pool, blocking the current thread
+    /// until the checkout completes.
+    ///
+    /// This is the same as `get`, for code that can't wait on a future. The
+    /// checkout runs on a current-thread runtime private to the call, which
+    /// also serves as the default executor for work the checkout spawns, e.g.
+    /// opening a connection for it. That work is finished before this
+    /// returns, so that it doesn't hold on to the pool's slots.
+    ///
+    /// This must not be called from within the runtime's worker threads: it
+    /// blocks the thread, which can deadlock the runtime if the checkout has to
+    /// wait for work running on the same thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime can't be created.
+    pub fn get_blocking(&self) -> Result<PooledConnection<M>, RunError<M::Error>> {
+        let mut runtime = Runtime::new().expect("failed to create a runtime for get_blocking");
+        let r = runtime.block_on(self.get());
+        let _ = runtime.run();
+        r
+    }
+}
@@ -0,0 +1,354 @@
+//! A `ManageConnection` that falls back to a standby backend.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use futures::future::{ok, Either};
+use futures::prelude::*;
+use tokio_executor::{DefaultExecutor, Executor};
+
+use {ConnectInfo, ManageConnection, Spawn};
+
+/// A connection manager that opens connections from a primary manager, and
+/// from a fallback manager while the primary is failing, e.g. to keep serving
+/// from a standby database while the primary is down.
+///
+/// Whenever opening a connection from the primary fails, one is opened from
+/// the fallback instead. Once `failure_threshold` attempts in a row have
+/// failed, the primary is considered down and connections are only opened
+/// from the fallback, while the primary is probed again every
+/// `probe_interval`. Probes are made as connections are opened, and in the
+/// background as fallback connections are checked out and returned. As soon
+/// as a probe succeeds, connections are opened from the primary again, and
+/// fallback connections are closed as they are returned to the pool. Idle
+/// ones can be closed straight away with `Pool::retain`.
+///
+/// Connections remember which manager opened them, so validation and breakage
+/// checks are dispatched to that manager. Errors from the primary are
+/// discarded when the fallback is used instead.
+#[derive(Debug)]
+pub struct Failover<M> {
+    primary: Arc<M>,
+    fallback: Arc<M>,
+    failure_threshold: u32,
+    probe_interval: Duration,
+    executor: Option<Box<dyn Spawn>>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    // The number of consecutive failed attempts to open a primary connection.
+    failures: u32,
+    // Whether the primary is considered down.
+    down: bool,
+    // When the primary was last probed while down.
+    last_probe: Instant,
+    // Whether a probe is running in the background.
+    probing: bool,
+}
+
+impl State {
+    fn primary_up(&mut self) {
+        self.failures = 0;
+        self.down = false;
+    }
+
+    fn primary_failed(&mut self, failure_threshold: u32) {
+        self.failures = self.failures.saturating_add(1);
+        if !self.down && self.failures >= failure_threshold {
+            self.down = true;
+            self.last_probe = Instant::now();
+        }
+    }
+
+    // Whether the primary should be probed, recording the probe if so.
+    fn start_probe(&mut self, probe_interval: Duration) -> bool {
+        let now = Instant::now();
+        if !self.down || self.probing || now - self.last_probe < probe_interval {
+            return false;
+        }
+        self.last_probe = now;
+        true
+    }
+}
+
+impl<M> Failover<M>
+where
+    M: ManageConnection,
+{
+    /// Create a new `Failover` from the primary and fallback managers.
+    pub fn new(primary: M, fallback: M) -> Failover<M> {
+        Failover {
+            primary: Arc::new(primary),
+            fallback: Arc::new(fallback),
+            failure_threshold: 3,
+            probe_interval: Duration::from_secs(10),
+            executor: None,
+            state: Arc::new(Mutex::new(State {
+                failures: 0,
+                down: false,
+                last_probe: Instant::now(),
+                probing: false,
+            })),
+        }
+    }
+
+    /// Sets how many attempts in a row to open a primary connection must fail
+    /// before the primary is considered down.
+    ///
+    /// Defaults to 3.
+    pub fn failure_threshold(mut self, failure_threshold: u32) -> Failover<M> {
+        assert!(
+            failure_threshold > 0,
+            "failure_threshold must be greater than zero!"
+        );
+        self.failure_threshold = failure_threshold;
+        self
+    }
+
+    /// Sets how often the primary is probed while it's considered down.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn probe_interval(mut self, probe_interval: Duration) -> Failover<M> {
+        assert!(
+            probe_interval > Duration::from_secs(0),
+            "probe_interval must be greater than zero!"
+        );
+        self.probe_interval = probe_interval;
+        self
+    }
+
+    /// Sets the executor that background probes of the primary are spawned
+    /// on, which should usually be the one given to `Builder::executor`.
+    ///
+    /// Defaults to the default tokio executor of the current thread. If there
+    /// is none, e.g. when the pool runs on a custom executor, background
+    /// probes are skipped and the primary is only probed as connections are
+    /// opened.
+    pub fn executor(mut self, executor: Box<dyn Spawn>) -> Failover<M> {
+        self.executor = Some(executor);
+        self
+    }
+
+    /// Returns the primary manager.
+    pub fn primary(&self) -> &M {
+        &self.primary
+    }
+
+    /// Returns the fallback manager.
+    pub fn fallback(&self) -> &M {
+        &self.fallback
+    }
+
+    /// Returns true if the primary is considered down, so connections are
+    /// opened from the fallback.
+    pub fn is_failed_over(&self) -> bool {
+        self.lock().down
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    // Whether a connection should be closed rather than reused, because it
+    // was opened from the fallback and the primary is back up.
+    fn is_stale(&self, conn: &FailoverConnection<M::Connection>) -> bool {
+        if !conn.fallback {
+            return false;
+        }
+        self.probe_in_background();
+        !self.lock().down
+    }
+
+    // Probes the primary on the executor, if one is due.
+    fn probe_in_background(&self) {
+        {
+            let mut state = self.lock();
+            if !state.start_probe(self.probe_interval) {
+                return;
+            }
+            state.probing = true;
+        }
+        let state = self.state.clone();
+        let probe = self.primary.connect().then(move |r| {
+            let mut state = state.lock().unwrap();
+            state.probing = false;
+            if r.is_ok() {
+                state.primary_up();
+            }
+            Ok(())
+        });
+        match self.executor {
+            Some(ref executor) => executor.spawn(Box::new(probe)),
+            None => {
+                if DefaultExecutor::current().spawn(Box::new(probe)).is_err() {
+                    self.lock().probing = false;
+                }
+            }
+        }
+    }
+}
+
+/// A connection opened by a `Failover` manager.
+///
+/// This dereferences to the underlying connection.
+#[derive(Debug)]
+pub struct FailoverConnection<C> {
+    conn: C,
+    fallback: bool,
+}
+
+impl<C> FailoverConnection<C> {
+    /// Returns true if the connection was opened by the fallback manager.
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+}
+
+impl<C> Deref for FailoverConnection<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.conn
+    }
+}
+
+impl<C> DerefMut for FailoverConnection<C> {
+    fn deref_mut(&mut self) -> &mut C {
+        &mut self.conn
+    }
+}
+
+impl<M> ManageConnection for Failover<M>
+where
+    M: ManageConnection,
+{
+    type Connection = FailoverConnection<M::Connection>;
+    type Error = M::Error;
+
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+        Box::new(self.connect_with_info().map(|(conn, _)| conn))
+    }
+
+    fn connect_with_info(
+        &self,
+    ) -> Box<dyn Future<Item = (Self::Connection, ConnectInfo), Error = Self::Error> + Send> {
+        let fallback = self.fallback.clone();
+        let connect_fallback = move || {
+            fallback.connect_with_info().map(|(conn, info)| {
+                let conn = FailoverConnection {
+                    conn,
+                    fallback: true,
+                };
+                (conn, info)
+            })
+        };
+        let try_primary = {
+            let mut state = self.lock();
+            !state.down || state.start_probe(self.probe_interval)
+        };
+        if !try_primary {
+            return Box::new(connect_fallback());
+        }
+
+        let state = self.state.clone();
+        let failure_threshold = self.failure_threshold;
+        Box::new(self.primary.connect_with_info().then(move |r| match r {
+            Ok((conn, info)) => {
+                state.lock().unwrap().primary_up();
+                let conn = FailoverConnection {
+                    conn,
+                    fallback: false,
+                };
+                Either::A(ok((conn, info)))
+            }
+            Err(_) => {
+                state.lock().unwrap().primary_failed(failure_threshold);
+                Either::B(connect_fallback())
+            }
+        }))
+    }
+
+    fn is_valid(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        if conn.fallback {
+            self.probe_in_background();
+        }
+        let FailoverConnection { conn, fallback } = conn;
+        let manager = if fallback {
+            &self.fallback
+        } else {
+            &self.primary
+        };
+        Box::new(manager.is_valid(conn).then(move |r| match r {
+            Ok(conn) => Ok(FailoverConnection { conn, fallback }),
+            Err((e, conn)) => Err((e, FailoverConnection { conn, fallback })),
+        }))
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        if self.is_stale(conn) {
+            return true;
+        }
+        if conn.fallback {
+            self.fallback.has_broken(&mut conn.conn)
+        } else {
+            self.primary.has_broken(&mut conn.conn)
+        }
+    }
+
+    fn is_valid_mut(
+        &self,
+        conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = (), Error = Self::Error> + Send> {
+        if conn.fallback {
+            self.fallback.is_valid_mut(&mut conn.conn)
+        } else {
+            self.primary.is_valid_mut(&mut conn.conn)
+        }
+    }
+
+    fn poll_broken(
+        &self,
+        conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = bool, Error = ()> + Send> {
+        if self.is_stale(conn) {
+            return Box::new(ok(true));
+        }
+        if conn.fallback {
+            self.fallback.poll_broken(&mut conn.conn)
+        } else {
+            self.primary.poll_broken(&mut conn.conn)
+        }
+    }
+
+    fn recycle(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        let FailoverConnection { conn, fallback } = conn;
+        let manager = if fallback {
+            &self.fallback
+        } else {
+            &self.primary
+        };
+        Box::new(manager.recycle(conn).then(move |r| match r {
+            Ok(conn) => Ok(FailoverConnection { conn, fallback }),
+            Err((e, conn)) => Err((e, FailoverConnection { conn, fallback })),
+        }))
+    }
+
+    fn on_pool_drop(&self, conn: Self::Connection) {
+        if conn.fallback {
+            self.fallback.on_pool_drop(conn.conn);
+        } else {
+            self.primary.on_pool_drop(conn.conn);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! A `ManageConnection` that routes connections to backends by key.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::prelude::*;
+
+use {
+    get_matching_connection, ConnectFuture, ConnectInfo, ManageConnection, Pool, PooledConnection,
+    RunError,
+};
+
+/// A connection manager over several backends, each identified by a key, e.g.
+/// the shards of a sharded database.
+///
+/// `Pool::get_keyed` checks out a connection to the backend with a given key,
+/// so e.g. a tenant can always be served by its own shard. Connections opened
+/// by the pool for any other reason, e.g. to maintain `min_idle`, are opened
+/// from each backend in turn, as with `RoundRobin`.
+///
+/// Connections remember which backend they're connected to, so validation and
+/// breakage checks are dispatched to that backend's manager.
+#[derive(Debug)]
+pub struct Keyed<K, M> {
+    backends: Vec<(K, M)>,
+    indices: HashMap<K, usize>,
+    next: AtomicUsize,
+    // The number of open connections to each backend.
+    counts: Arc<Vec<AtomicU32>>,
+}
+
+impl<K, M> Keyed<K, M>
+where
+    K: Clone + Eq + Hash + fmt::Debug + Send + Sync + 'static,
+    M: ManageConnection,
+{
+    /// Create a new `Keyed` over the given backends, each with its key and
+    /// manager.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `backends` is empty, or if two backends have the same key.
+    pub fn new(backends: Vec<(K, M)>) -> Keyed<K, M> {
+        assert!(!backends.is_empty(), "Keyed requires at least one backend!");
+        let mut indices = HashMap::new();
+        for (index, (key, _)) in backends.iter().enumerate() {
+            assert!(
+                indices.insert(key.clone(), index).is_none(),
+                "Keyed backends must have distinct keys!"
+            );
+        }
+        let counts = backends.iter().map(|_| AtomicU32::new(0)).collect();
+        Keyed {
+            backends,
+            indices,
+            next: AtomicUsize::new(0),
+            counts: Arc::new(counts),
+        }
+    }
+
+    /// Returns the keys of the backends, in the order connections are opened
+    /// from them.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.backends.iter().map(|(key, _)| key)
+    }
+
+    /// Returns the manager of the backend with the given key, if any.
+    pub fn manager(&self, key: &K) -> Option<&M> {
+        self.indices.get(key).map(|&index| &self.backends[index].1)
+    }
+
+    // Open a connection to the backend at `index`.
+    fn connect_to(&self, index: usize) -> ConnectFuture<Keyed<K, M>> {
+        let key = self.backends[index].0.clone();
+        let counts = self.counts.clone();
+        Box::new(
+            self.backends[index]
+                .1
+                .connect_with_info()
+                .map(move |(conn, info)| {
+                    counts[index].fetch_add(1, Ordering::Relaxed);
+                    let conn = KeyedConnection {
+                        conn,
+                        backend: Backend { index, key, counts },
+                    };
+                    (conn, info)
+                }),
+        )
+    }
+}
+
+/// A connection opened by a `Keyed` manager.
+///
+/// This dereferences to the underlying connection.
+#[derive(Debug)]
+pub struct KeyedConnection<K, C> {
+    conn: C,
+    backend: Backend<K>,
+}
+
+impl<K, C> KeyedConnection<K, C> {
+    /// Returns the key of the backend this connection is connected to.
+    pub fn key(&self) -> &K {
+        &self.backend.key
+    }
+}
+
+impl<K, C> Deref for KeyedConnection<K, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.conn
+    }
+}
+
+impl<K, C> DerefMut for KeyedConnection<K, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        &mut self.conn
+    }
+}
+
+// Keeps the per-backend connection count up to date.
+#[derive(Debug)]
+struct Backend<K> {
+    index: usize,
+    key: K,
+    counts: Arc<Vec<AtomicU32>>,
+}
+
+impl<K> Drop for Backend<K> {
+    fn drop(&mut self) {
+        self.counts[self.index].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<K, M> ManageConnection for Keyed<K, M>
+where
+    K: Clone + Eq + Hash + fmt::Debug + Send + Sync + 'static,
+    M: ManageConnection,
+{
+    type Connection = KeyedConnection<K, M::Connection>;
+    type Error = M::Error;
+
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+        Box::new(self.connect_with_info().map(|(conn, _)| conn))
+    }
+
+    fn connect_with_info(
+        &self,
+    ) -> Box<dyn Future<Item = (Self::Connection, ConnectInfo), Error = Self::Error> + Send> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.backends.len();
+        self.connect_to(index)
+    }
+
+    fn is_valid(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        let KeyedConnection { conn, backend } = conn;
+        Box::new(
+            self.backends[backend.index]
+                .1
+                .is_valid(conn)
+                .then(move |r| match r {
+                    Ok(conn) => Ok(KeyedConnection { conn, backend }),
+                    Err((e, conn)) => Err((e, KeyedConnection { conn, backend })),
+                }),
+        )
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        self.backends[conn.backend.index]
+            .1
+            .has_broken(&mut conn.conn)
+    }
+
+    fn is_valid_mut(
+        &self,
+        conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = (), Error = Self::Error> + Send> {
+        self.backends[conn.backend.index]
+            .1
+            .is_valid_mut(&mut conn.conn)
+    }
+
+    fn poll_broken(
+        &self,
+        conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = bool, Error = ()> + Send> {
+        self.backends[conn.backend.index]
+            .1
+            .poll_broken(&mut conn.conn)
+    }
+
+    fn recycle(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        let KeyedConnection { conn, backend } = conn;
+        Box::new(
+            self.backends[backend.index]
+                .1
+                .recycle(conn)
+                .then(move |r| match r {
+                    Ok(conn) => Ok(KeyedConnection { conn, backend }),
+                    Err((e, conn)) => Err((e, KeyedConnection { conn, backend })),
+                }),
+        )
+    }
+
+    fn on_pool_drop(&self, conn: Self::Connection) {
+        let KeyedConnection { conn, backend } = conn;
+        self.backends[backend.index].1.on_pool_drop(conn);
+    }
+}
+
+/// Information about the connections to one backend of a `Keyed` pool,
+/// returned by `Pool::keyed_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct KeyState {
+    /// The number of open connections to the backend.
+    pub connections: u32,
+    /// The number of idle connections to the backend.
+    pub idle_connections: u32,
+}
+
+impl<K, M> Pool<Keyed<K, M>>
+where
+    K: Clone + Eq + Hash + fmt::Debug + Send + Sync + 'static,
+    M: ManageConnection,
+{
+    /// Retrieves a connection to the backend with the given key.
+    ///
+    /// An idle connection to that backend is handed out if there is one.
+    /// Otherwise one is opened, as long as the pool has room. If it doesn't,
+    /// an idle connection to another backend is closed to make room, or if
+    /// every connection is checked out, the checkout waits for one to be
+    /// returned and swaps it for a connection to the right backend. The
+    /// checkout waits at most the pool's `connection_timeout` in all.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the manager has no backend with the given key.
+    pub fn get_keyed(
+        &self,
+        key: K,
+    ) -> impl Future<Item = PooledConnection<Keyed<K, M>>, Error = RunError<M::Error>> + Send {
+        let index = match self.manager().indices.get(&key) {
+            Some(&index) => index,
+            None => panic!("No backend with key {:?}!", key),
+        };
+        let inner = self.inner.clone();
+        let permit = self.reserve_slot();
+        let reserved = permit.is_some();
+        get_matching_connection(
+            self.inner.clone(),
+            self.connection_timeout,
+            reserved,
+            Arc::new(move |conn: &KeyedConnection<K, M::Connection>| conn.backend.index == index),
+            move |manager: &Keyed<K, M>| manager.connect_to(index),
+        )
+        .map(move |conn| PooledConnection {
+            pool: inner,
+            conn: Some(conn),
+            permit,
+        })
+    }
+
+    /// Returns the number of open and idle connections to each backend.
+    pub fn keyed_state(&self) -> HashMap<K, KeyState> {
+        let manager = self.manager();
+        let mut state = manager
+            .backends
+            .iter()
+            .zip(manager.counts.iter())
+            .map(|((key, _), count)| {
+                let state = KeyState {
+                    connections: count.load(Ordering::Relaxed),
+                    idle_connections: 0,
+                };
+                (key.clone(), state)
+            })
+            .collect::<HashMap<_, _>>();
+        let locked = self.inner.lock();
+        for conn in &locked.conns {
+            if let Some(state) = state.get_mut(conn.conn.conn.key()) {
+                state.idle_connections += 1;
+            }
+        }
+        state
+    }
+}
@@ -12,30 +12,57 @@
 #![deny(missing_docs, missing_debug_implementations)]
 
 extern crate futures;
+#[cfg(feature = "std-future")]
+extern crate futures03;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "blocking")]
+extern crate tokio;
 extern crate tokio_executor;
 extern crate tokio_timer;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
+use std::any::{Any, TypeId};
 use std::borrow::BorrowMut;
 use std::cmp::{max, min};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
 use std::error;
 use std::fmt;
 use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard, Weak};
 use std::time::{Duration, Instant};
 
-use futures::future::{lazy, loop_fn, ok, Either, Loop};
+use futures::future::{err, join_all, lazy, loop_fn, ok, Either, Loop};
 use futures::prelude::*;
-use futures::stream::FuturesUnordered;
+use futures::stream::{self, FuturesUnordered};
 use futures::sync::oneshot;
-use tokio_executor::spawn;
-use tokio_timer::{Interval, Timeout};
+use tokio_timer::{timeout, Delay, Interval, Timeout};
 
+#[cfg(feature = "blocking")]
+mod blocking;
+mod failover;
+mod keyed;
+#[cfg(feature = "metrics")]
+mod pool_metrics;
+mod round_robin;
+#[cfg(feature = "std-future")]
+mod std_future;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod trace;
 mod util;
 use util::*;
 
+pub use failover::{Failover, FailoverConnection};
+pub use keyed::{KeyState, Keyed, KeyedConnection};
+pub use round_robin::{RoundRobin, RoundRobinConnection};
+
 /// A trait which provides connection-specific functionality.
 pub trait ManageConnection: Send + Sync + 'static {
     /// The connection type this manager deals with.
@@ -44,14 +71,129 @@ pub trait ManageConnection: Send + Sync + 'static {
     type Error: Send + 'static;
 
     /// Attempts to create a new connection.
-    fn connect(&self) -> Box<Future<Item = Self::Connection, Error = Self::Error> + Send>;
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send>;
+    /// Attempts to create a new connection, along with information about it
+    /// that the pool keeps with the connection.
+    ///
+    /// The default implementation calls `connect` and records nothing.
+    #[allow(clippy::type_complexity)]
+    fn connect_with_info(
+        &self,
+    ) -> Box<dyn Future<Item = (Self::Connection, ConnectInfo), Error = Self::Error> + Send> {
+        Box::new(self.connect().map(|conn| (conn, ConnectInfo::new())))
+    }
     /// Determines if the connection is still connected to the database.
+    ///
+    /// The default implementation calls `is_valid_mut`, so managers only need
+    /// to implement one of the two.
+    #[allow(clippy::type_complexity)]
     fn is_valid(
         &self,
-        conn: Self::Connection,
-    ) -> Box<Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>;
+        mut conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        Box::new(self.is_valid_mut(&mut conn).then(move |r| match r {
+            Ok(()) => Ok(conn),
+            Err(e) => Err((e, conn)),
+        }))
+    }
+    /// Determines if the connection is still connected to the database,
+    /// without taking ownership of it.
+    ///
+    /// The pool only calls `is_valid`, whose default implementation calls
+    /// this. The default implementation of this reports every connection as
+    /// valid, so managers that implement `is_valid` needn't implement it.
+    fn is_valid_mut(
+        &self,
+        _conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = (), Error = Self::Error> + Send> {
+        Box::new(ok(()))
+    }
     /// Synchronously determine if the connection is no longer usable, if possible.
     fn has_broken(&self, conn: &mut Self::Connection) -> bool;
+    /// Asynchronously determine if the connection is no longer usable, if
+    /// possible. This is checked as a connection is returned to the pool, and
+    /// should be much cheaper than `is_valid`. If the future fails the
+    /// connection is treated as broken.
+    ///
+    /// The default implementation calls `has_broken`.
+    fn poll_broken(
+        &self,
+        conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = bool, Error = ()> + Send> {
+        Box::new(ok(self.has_broken(conn)))
+    }
+    /// Resets a connection's session state as it is returned to the pool,
+    /// e.g. rolling back an open transaction or running `DISCARD ALL`.
+    ///
+    /// Unlike `is_valid`, which checks that a connection is still alive, this
+    /// makes sure that it's clean for the next user. It's called on every
+    /// connection returned to the pool that isn't broken, before it's made
+    /// idle again. If it fails, the error is reported to the error sink and the
+    /// connection is closed.
+    ///
+    /// The default implementation leaves the connection as it is.
+    #[allow(clippy::type_complexity)]
+    fn recycle(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        Box::new(ok(conn))
+    }
+    /// Closes an idle connection as the last handle to its pool is dropped,
+    /// e.g. by sending the protocol's goodbye message.
+    ///
+    /// This is called from `Drop`, so there may be no executor to run a
+    /// future on: it must close the connection synchronously, on a best
+    /// effort basis, and shouldn't block for long. It isn't called for
+    /// connections that are checked out at the time, nor for connections
+    /// closed by `Pool::shutdown`.
+    ///
+    /// The default implementation just drops the connection.
+    fn on_pool_drop(&self, conn: Self::Connection) {
+        mem::drop(conn);
+    }
+}
+
+/// Information that a `ManageConnection` records about a connection when it
+/// opens it, e.g. the server version or backend process id, stored by type.
+#[derive(Default)]
+pub struct ConnectInfo {
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl ConnectInfo {
+    /// Creates an empty `ConnectInfo`.
+    pub fn new() -> ConnectInfo {
+        ConnectInfo::default()
+    }
+
+    /// Stores `value`, replacing any previous value of the same type.
+    pub fn insert<T>(&mut self, value: T)
+    where
+        T: Send + Sync + 'static,
+    {
+        self.values.insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    /// Returns the stored value of type `T`, if any.
+    pub fn get<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+}
+
+impl fmt::Debug for ConnectInfo {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ConnectInfo")
+            .field("len", &self.values.len())
+            .finish()
+    }
 }
 
 /// bb8's error type.
@@ -61,6 +203,91 @@ pub enum RunError<E> {
     User(E),
     /// bb8 attempted to get a connection but the provided timeout was exceeded.
     TimedOut,
+    /// bb8 refused to wait for a connection because recent attempts to open
+    /// one have failed.
+    CircuitOpen,
+    /// The pool has been shut down and no longer hands out connections.
+    PoolClosed,
+    /// bb8 refused to wait for a connection because too many other callers
+    /// are already waiting, or because the pool's `QueueStrategy` is `Fail`.
+    QueueFull,
+}
+
+impl<E> RunError<E> {
+    /// Returns true if this error is a `RunError::TimedOut`.
+    pub fn is_timeout(&self) -> bool {
+        matches!(*self, RunError::TimedOut)
+    }
+
+    /// Returns true if the pool couldn't hand out a connection, i.e. this
+    /// isn't a `RunError::User`, e.g. to answer a request with 503 Service
+    /// Unavailable rather than 500 Internal Server Error.
+    pub fn is_pool_error(&self) -> bool {
+        !matches!(*self, RunError::User(_))
+    }
+
+    /// Returns the error returned from user code, if this is a
+    /// `RunError::User`.
+    pub fn as_user(&self) -> Option<&E> {
+        match *self {
+            RunError::User(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Converts this into the error returned from user code, if this is a
+    /// `RunError::User`.
+    pub fn into_user(self) -> Option<E> {
+        match self {
+            RunError::User(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Maps the error returned from user code with `f`, leaving other errors
+    /// unchanged.
+    pub fn map_user<F, O>(self, f: O) -> RunError<F>
+    where
+        O: FnOnce(E) -> F,
+    {
+        match self {
+            RunError::User(e) => RunError::User(f(e)),
+            RunError::TimedOut => RunError::TimedOut,
+            RunError::CircuitOpen => RunError::CircuitOpen,
+            RunError::PoolClosed => RunError::PoolClosed,
+            RunError::QueueFull => RunError::QueueFull,
+        }
+    }
+
+    /// Converts the error returned from user code with `Into`, leaving other
+    /// errors unchanged.
+    pub fn err_into<F>(self) -> RunError<F>
+    where
+        E: Into<F>,
+    {
+        self.map_user(Into::into)
+    }
+}
+
+impl<E> RunError<E>
+where
+    E: error::Error + Send + Sync + 'static,
+{
+    /// Boxes this error, e.g. to propagate it into a web framework's error
+    /// type, most of which can be built from a boxed error.
+    ///
+    /// `?` also converts a `RunError` into a boxed error, but this is handy
+    /// with `map_err` where the target type can't be inferred. The boxed
+    /// error can be downcast back to `RunError<E>`.
+    pub fn into_boxed_error(self) -> Box<dyn error::Error + Send + Sync> {
+        Box::new(self)
+    }
+}
+
+impl<E> From<E> for RunError<E> {
+    fn from(e: E) -> RunError<E> {
+        RunError::User(e)
+    }
 }
 
 impl<E> fmt::Display for RunError<E>
@@ -71,6 +298,9 @@ where
         match *self {
             RunError::User(ref err) => write!(f, "{}", err),
             RunError::TimedOut => write!(f, "Timed out in bb8"),
+            RunError::CircuitOpen => write!(f, "Circuit breaker open in bb8"),
+            RunError::PoolClosed => write!(f, "Pool closed in bb8"),
+            RunError::QueueFull => write!(f, "Wait queue full in bb8"),
         }
     }
 }
@@ -82,19 +312,181 @@ where
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
             RunError::User(ref err) => Some(err),
-            RunError::TimedOut => None,
+            RunError::TimedOut
+            | RunError::CircuitOpen
+            | RunError::PoolClosed
+            | RunError::QueueFull => None,
+        }
+    }
+}
+
+/// An error reported when a connection has been checked out for longer than
+/// the pool's leak detection threshold.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct LeakError {
+    /// How long the connection had been checked out when it was reported.
+    pub checked_out_for: Duration,
+}
+
+impl fmt::Display for LeakError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Connection checked out for {:?}, possible leak detected in bb8",
+            self.checked_out_for
+        )
+    }
+}
+
+impl error::Error for LeakError {}
+
+/// An error reported when an attempt to open a connection takes longer than
+/// the pool's create timeout.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CreateTimeoutError {
+    /// The create timeout that was exceeded.
+    pub timeout: Duration,
+}
+
+impl fmt::Display for CreateTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {:?} opening a connection in bb8",
+            self.timeout
+        )
+    }
+}
+
+impl error::Error for CreateTimeoutError {}
+
+/// An error reported by `Builder::build_with_result` when the pool couldn't
+/// open its configured minimum number of connections.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct PartialInit<E> {
+    /// The number of connections that were opened.
+    pub connections: u32,
+    /// The number of connections the pool was configured to open.
+    pub wanted: u32,
+    /// The error from opening a connection, or `None` if opening them timed
+    /// out.
+    pub error: Option<E>,
+}
+
+impl<E> fmt::Display for PartialInit<E>
+where
+    E: error::Error + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Opened {} of {} connections building pool in bb8",
+            self.connections, self.wanted
+        )?;
+        match self.error {
+            Some(ref err) => write!(f, ": {}", err),
+            None => write!(f, ": timed out"),
+        }
+    }
+}
+
+impl<E> error::Error for PartialInit<E>
+where
+    E: error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.error
+            .as_ref()
+            .map(|err| err as &(dyn error::Error + 'static))
+    }
+}
+
+/// An inconsistency in a `Builder`'s configuration, found by
+/// `Builder::validate`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum BuilderError {
+    /// A setting that must be greater than zero is zero. Holds the setting's
+    /// name.
+    Zero(&'static str),
+    /// A minimum number of connections is larger than `max_size`. Holds the
+    /// setting's name.
+    ExceedsMaxSize(&'static str),
+    /// The base delay of `connection_retry` is larger than its max delay.
+    RetryDelay,
+    /// The target percentile of `adaptive_idle` is not between 0 and 100.
+    TargetPercentile(f64),
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuilderError::Zero(setting) => write!(f, "{} must be greater than zero", setting),
+            BuilderError::ExceedsMaxSize(setting) => {
+                write!(f, "{} must be no larger than max_size", setting)
+            }
+            BuilderError::RetryDelay => f.write_str("base_delay must be no larger than max_delay"),
+            BuilderError::TargetPercentile(p) => {
+                write!(f, "target_percentile must be between 0 and 100, not {}", p)
+            }
         }
     }
 }
 
+impl error::Error for BuilderError {}
+
+/// What the pool was doing when an error sent to an `ErrorSink` occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorContext {
+    /// Opening a connection, e.g. to maintain `min_idle` or for a checkout
+    /// waiting on one.
+    Connect,
+    /// Opening a connection to replace ones closed by the reaper.
+    Reap,
+    /// Validating an idle connection before it was checked out, with
+    /// `test_on_check_out`.
+    CheckOut,
+    /// Validating a connection as it was returned to the pool, with
+    /// `test_on_return`.
+    Return,
+    /// Checking an idle connection, with `keepalive_interval` or
+    /// `background_validation`.
+    Keepalive,
+    /// Tearing down a connection as it was closed, with
+    /// `ConnectionCustomizer::on_close`.
+    Close,
+}
+
 /// A trait to receive errors generated by connection management that aren't
 /// tied to any particular caller.
 pub trait ErrorSink<E>: fmt::Debug + Send + Sync + 'static {
     /// Receive an error
     fn sink(&self, error: E);
 
+    /// Receive an error, along with what the pool was doing when it occurred.
+    /// The pool always reports errors through this method.
+    ///
+    /// The default implementation ignores the context and calls `sink`.
+    fn sink_with_context(&self, error: E, _context: ErrorContext) {
+        self.sink(error)
+    }
+
+    /// Receive a report of a possibly leaked connection.
+    ///
+    /// The default implementation ignores the report.
+    fn sink_leak(&self, _error: LeakError) {}
+
+    /// Receive a report of an attempt to open a connection that timed out.
+    ///
+    /// The default implementation ignores the report.
+    fn sink_create_timeout(&self, _error: CreateTimeoutError) {}
+
     /// Clone this sink.
-    fn boxed_clone(&self) -> Box<ErrorSink<E>>;
+    fn boxed_clone(&self) -> Box<dyn ErrorSink<E>>;
 }
 
 /// An `ErrorSink` implementation that does nothing.
@@ -104,18 +496,333 @@ pub struct NopErrorSink;
 impl<E> ErrorSink<E> for NopErrorSink {
     fn sink(&self, _: E) {}
 
-    fn boxed_clone(&self) -> Box<ErrorSink<E>> {
-        Box::new(self.clone())
+    fn boxed_clone(&self) -> Box<dyn ErrorSink<E>> {
+        Box::new(*self)
+    }
+}
+
+/// A trait which provides the executor that the pool runs its background
+/// work, such as opening and reaping connections, on.
+pub trait Spawn: fmt::Debug + Send + Sync + 'static {
+    /// Spawn a future onto the executor.
+    fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send>);
+}
+
+/// A `Spawn` implementation that uses the default tokio executor for the
+/// current thread.
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultSpawn;
+
+impl Spawn for DefaultSpawn {
+    fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send>) {
+        tokio_executor::spawn(future);
+    }
+}
+
+/// A trait which provides the current time to the pool.
+///
+/// The pool uses it to track connection lifetimes, idle durations and
+/// checkout durations, so a custom clock can drive the reaper
+/// deterministically.
+pub trait Clock: fmt::Debug + Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// A `Clock` implementation that uses the system's monotonic clock.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A trait which provides functionality to customize connections as they are
+/// checked out of and returned to the pool.
+pub trait ConnectionCustomizer<C, E: Send + 'static>: fmt::Debug + Send + Sync + 'static {
+    /// Called with a connection immediately before it is handed to a pool user,
+    /// after any `test_on_check_out` validation has passed.
+    ///
+    /// If the returned future fails the connection is dropped, and the error
+    /// is returned to the caller waiting for the connection.
+    ///
+    /// The default implementation does nothing.
+    fn on_acquire(&self, _conn: &mut C) -> Box<dyn Future<Item = (), Error = E> + Send> {
+        Box::new(ok(()))
+    }
+
+    /// Called with a connection when a pool user gives it back to the pool,
+    /// before it is checked for breakage and re-queued.
+    ///
+    /// The default implementation does nothing.
+    fn on_release(&self, _conn: &mut C) {}
+
+    /// Called with a connection as the pool closes it, e.g. because it broke,
+    /// expired or was reaped, to tear it down asynchronously, e.g. by sending
+    /// the protocol's goodbye message.
+    ///
+    /// If a future is returned, it's spawned on the pool's executor and the
+    /// connection is dropped once it completes, while the connection's slot
+    /// is already free for a replacement. Errors are reported to the error
+    /// sink. A teardown doesn't hold up the others, but the future returned by
+    /// operations that close connections, such as `Pool::shutdown`, resolves
+    /// only once their teardowns have completed. If `None` is returned the
+    /// connection is dropped right away. This may be called with the pool
+    /// lock held, so it should only start the teardown.
+    ///
+    /// The default implementation returns `None`.
+    fn on_close(&self, _conn: C) -> Option<Box<dyn Future<Item = (), Error = E> + Send>> {
+        None
+    }
+}
+
+/// A `ConnectionCustomizer` implementation that does nothing.
+#[derive(Debug, Clone, Copy)]
+pub struct NopConnectionCustomizer;
+
+impl<C, E: Send + 'static> ConnectionCustomizer<C, E> for NopConnectionCustomizer {}
+
+/// A trait to receive notifications of events in the lifecycle of a pool and
+/// its connections, e.g. to record metrics.
+///
+/// The hooks are called inline as the pool does its work, so they should be
+/// cheap. They all do nothing by default.
+///
+/// With the `metrics` feature, the pool also reports its events through the
+/// [`metrics`](https://docs.rs/metrics) crate facade: checkout wait times as
+/// the `bb8.checkout_duration` histogram, and the `bb8.connects`,
+/// `bb8.connect_errors` and `bb8.timeouts` counters. The numbers of open and
+/// idle connections and of waiting checkouts are reported as the
+/// `bb8.connections`, `bb8.idle` and `bb8.waiters` gauges. The metrics are
+/// labelled with the pool's name as `pool`, if it has one, and are registered
+/// with the recorder installed when the pool is built.
+pub trait PoolObserver: fmt::Debug + Send + Sync + 'static {
+    /// Called when a connection is handed to a pool user, with the time the
+    /// user spent waiting for it.
+    fn on_acquire(&self, _wait: Duration) {}
+
+    /// Called when a pool user gives a connection back to the pool.
+    fn on_release(&self) {}
+
+    /// Called when a new connection has been opened.
+    fn on_connect(&self) {}
+
+    /// Called when opening a new connection has failed, after any retries.
+    fn on_connect_error(&self) {}
+
+    /// Called when a checkout times out waiting for a connection.
+    fn on_timeout(&self) {}
+
+    /// Called when the reaper closes connections, with the number closed.
+    fn on_reap(&self, _count: u32) {}
+
+    /// Called when the pool has failed to maintain `min_idle` for longer than
+    /// `Builder::min_idle_alert_threshold`, with the number of idle
+    /// connections, `min_idle`, and how long it has been failing to.
+    fn on_min_idle_unmet(&self, _idle: u32, _min_idle: u32, _unmet_for: Duration) {}
+}
+
+/// A `PoolObserver` implementation that does nothing.
+#[derive(Debug, Clone, Copy)]
+pub struct NopPoolObserver;
+
+impl PoolObserver for NopPoolObserver {}
+
+/// The order in which idle connections are handed out by a `Pool`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionOrder {
+    /// Hand out the connection that has been idle the longest, keeping all
+    /// connections equally warm.
+    Fifo,
+    /// Hand out the connection that was most recently returned, letting
+    /// excess connections go idle so they can be reaped.
+    Lifo,
+}
+
+impl<C: Send> SelectionPolicy<C> for ConnectionOrder {
+    fn select(&self, conns: &IdleConnections<C>) -> Option<usize> {
+        match (*self, conns.len()) {
+            (_, 0) => None,
+            (ConnectionOrder::Fifo, _) => Some(0),
+            (ConnectionOrder::Lifo, len) => Some(len - 1),
+        }
+    }
+}
+
+/// A trait which picks the idle connection a `Pool` hands out.
+///
+/// `ConnectionOrder` implements this for the common cases, but a custom policy
+/// can pick connections by any metric, e.g. the backend they're connected to.
+pub trait SelectionPolicy<C: Send>: fmt::Debug + Send + Sync + 'static {
+    /// Returns the index in `conns` of the connection to hand out, or `None`
+    /// to hand out none of them.
+    fn select(&self, conns: &IdleConnections<C>) -> Option<usize>;
+}
+
+/// A pool's idle connections, in the order they became idle.
+pub struct IdleConnections<'a, C: Send> {
+    conns: &'a VecDeque<IdleConn<C>>,
+    now: Instant,
+}
+
+impl<'a, C: Send> IdleConnections<'a, C> {
+    /// Returns the number of idle connections.
+    pub fn len(&self) -> usize {
+        self.conns.len()
+    }
+
+    /// Returns true if there are no idle connections.
+    pub fn is_empty(&self) -> bool {
+        self.conns.is_empty()
+    }
+
+    /// Returns the idle connection at `index`.
+    pub fn get(&self, index: usize) -> Option<IdleConnection<'a, C>> {
+        let now = self.now;
+        self.conns
+            .get(index)
+            .map(|conn| IdleConnection { conn, now })
+    }
+
+    /// Returns an iterator over the idle connections.
+    pub fn iter(&self) -> impl Iterator<Item = IdleConnection<'a, C>> + 'a {
+        let now = self.now;
+        self.conns
+            .iter()
+            .map(move |conn| IdleConnection { conn, now })
+    }
+}
+
+impl<C: Send> fmt::Debug for IdleConnections<'_, C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("IdleConnections")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// An idle connection considered by a `SelectionPolicy` or `Pool::retain`.
+pub struct IdleConnection<'a, C: Send> {
+    conn: &'a IdleConn<C>,
+    now: Instant,
+}
+
+impl<'a, C: Send> IdleConnection<'a, C> {
+    /// Returns the connection.
+    pub fn connection(&self) -> &'a C {
+        &self.conn.conn.conn
     }
+
+    /// Returns how long ago the connection was opened.
+    pub fn age(&self) -> Duration {
+        self.now - self.conn.conn.birth
+    }
+
+    /// Returns how long the connection has been idle.
+    pub fn idle_for(&self) -> Duration {
+        self.now - self.conn.idle_start
+    }
+
+    /// Returns the number of times the connection has been checked out.
+    pub fn uses(&self) -> u64 {
+        self.conn.conn.uses
+    }
+
+    /// Returns the information the manager recorded when it opened the
+    /// connection.
+    pub fn connect_info(&self) -> &'a ConnectInfo {
+        &self.conn.conn.info
+    }
+}
+
+impl<C: Send> fmt::Debug for IdleConnection<'_, C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("IdleConnection")
+            .field("age", &self.age())
+            .field("idle_for", &self.idle_for())
+            .field("uses", &self.uses())
+            .finish()
+    }
+}
+
+/// How urgently a checkout needs a connection.
+///
+/// When a connection becomes available it is handed to the longest waiting
+/// checkout of the highest priority. Checkouts of the same priority are served
+/// in the order they started waiting.
+///
+/// A steady stream of higher priority checkouts can starve lower priority ones
+/// indefinitely, so `Low` is best reserved for work that can tolerate timing
+/// out, e.g. background batch jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Served only once no other checkouts are waiting.
+    Low,
+    /// The priority of `get` and `run`.
+    #[default]
+    Normal,
+    /// Served before any other checkouts.
+    High,
+}
+
+/// What happens to a connection once a `Pool::run_with_disposition` closure
+/// is done with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Disposition {
+    /// Return the connection to the pool, as `run` does.
+    #[default]
+    Keep,
+    /// Close the connection instead of returning it to the pool.
+    Discard,
+}
+
+/// What a checkout does when every connection is checked out and the pool
+/// can't open any more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueStrategy {
+    /// Wait for a connection to be returned, until the checkout's timeout.
+    Wait,
+    /// Fail immediately with `RunError::QueueFull`.
+    Fail,
+    /// Wait for a connection to be returned for at most the given duration,
+    /// or the checkout's timeout if that is shorter, before failing with
+    /// `RunError::TimedOut`.
+    WaitBounded(Duration),
 }
 
 /// Information about the state of a `Pool`.
+///
+/// `connections`, `idle_connections`, `pending_connections` and `waiters`
+/// describe the pool as it is right now. The other fields are cumulative,
+/// counting from when the pool was built or `Pool::reset_stats` was last
+/// called.
+#[non_exhaustive]
 pub struct State {
     /// The number of connections currently being managed by the pool.
     pub connections: u32,
     /// The number of idle connections.
     pub idle_connections: u32,
-    _p: (),
+    /// The number of connections currently being opened.
+    pub pending_connections: u32,
+    /// The number of checkouts currently waiting for a connection.
+    pub waiters: u32,
+    /// The number of checkouts that had to wait for a connection.
+    pub wait_count: u64,
+    /// The total time spent waiting for connections by those checkouts.
+    pub total_wait: Duration,
+    /// The longest time any single checkout waited for a connection.
+    pub max_wait: Duration,
+    /// The number of connections the pool has opened.
+    pub connections_created: u64,
+    /// The number of times the pool has failed to open a connection, after
+    /// any retries.
+    pub connection_errors: u64,
+    /// The number of idle connections the reaper has closed, for exceeding
+    /// `idle_timeout` or `max_lifetime` or because the pool was shrunk. Each
+    /// reaper run's share is also reported to `PoolObserver::on_reap`.
+    pub connections_reaped: u64,
 }
 
 impl fmt::Debug for State {
@@ -123,10 +830,28 @@ impl fmt::Debug for State {
         fmt.debug_struct("State")
             .field("connections", &self.connections)
             .field("idle_connections", &self.idle_connections)
+            .field("pending_connections", &self.pending_connections)
+            .field("waiters", &self.waiters)
+            .field("wait_count", &self.wait_count)
+            .field("total_wait", &self.total_wait)
+            .field("max_wait", &self.max_wait)
+            .field("connections_created", &self.connections_created)
+            .field("connection_errors", &self.connection_errors)
+            .field("connections_reaped", &self.connections_reaped)
             .finish()
     }
 }
 
+/// Information about a single idle connection in a `Pool`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConnectionInfo {
+    /// How long ago the connection was opened.
+    pub age: Duration,
+    /// How long the connection has been idle.
+    pub idle_for: Duration,
+}
+
 #[derive(Debug)]
 struct Conn<C>
 where
@@ -134,6 +859,42 @@ where
 {
     conn: C,
     birth: Instant,
+    generation: u64,
+    uses: u64,
+    id: u64,
+    // Set through `PooledConnection::metadata`, by type.
+    metadata: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    info: ConnectInfo,
+}
+
+impl<C> Conn<C>
+where
+    C: Send,
+{
+    // Swap out the underlying connection, keeping the bookkeeping that goes with it.
+    fn replace<D: Send>(self, conn: D) -> (C, Conn<D>) {
+        let Conn {
+            conn: old,
+            birth,
+            generation,
+            uses,
+            id,
+            metadata,
+            info,
+        } = self;
+        (
+            old,
+            Conn {
+                conn,
+                birth,
+                generation,
+                uses,
+                id,
+                metadata,
+                info,
+            },
+        )
+    }
 }
 
 struct IdleConn<C>
@@ -142,17 +903,22 @@ where
 {
     conn: Conn<C>,
     idle_start: Instant,
+    last_active: Instant,
+    // When the connection was last known to be valid, if it hasn't been used
+    // since.
+    last_validated: Option<Instant>,
 }
 
 impl<C> IdleConn<C>
 where
     C: Send,
 {
-    fn make_idle(conn: Conn<C>) -> IdleConn<C> {
-        let now = Instant::now();
+    fn make_idle(conn: Conn<C>, now: Instant) -> IdleConn<C> {
         IdleConn {
-            conn: conn,
+            conn,
             idle_start: now,
+            last_active: now,
+            last_validated: None,
         }
     }
 }
@@ -160,36 +926,123 @@ where
 /// A builder for a connection pool.
 #[derive(Debug)]
 pub struct Builder<M: ManageConnection> {
+    /// The name, if any, identifying the pool.
+    name: Option<String>,
     /// The maximum number of connections allowed.
     max_size: u32,
     /// The minimum idle connection count the pool will attempt to maintain.
     min_idle: Option<u32>,
+    /// The minimum total connection count the pool will attempt to maintain.
+    min_connections: Option<u32>,
+    /// Whether `min_idle` and `min_connections` are only maintained after the first checkout.
+    lazy_min_idle: bool,
     /// Whether or not to test the connection on checkout.
     test_on_check_out: bool,
+    /// The policy picking which idle connection is checked out.
+    selection_policy: Box<dyn SelectionPolicy<M::Connection>>,
+    /// Whether or not to test the connection when it is returned to the pool.
+    test_on_return: bool,
+    /// Whether connections returned broken are replaced straight away.
+    replace_broken: bool,
     /// The maximum lifetime, if any, that a connection is allowed.
     max_lifetime: Option<Duration>,
+    /// Whether idle connections past `max_lifetime` are closed as they're checked out.
+    expire_on_check_out: bool,
+    /// The maximum number of times, if any, that a connection can be checked out.
+    max_uses: Option<u64>,
+    /// The duration, if any, after which checked out connections are reported as leaked.
+    leak_detection_threshold: Option<Duration>,
+    /// How long the pool can fail to maintain `min_idle` before it's reported.
+    min_idle_alert_threshold: Duration,
     /// The duration, if any, after which idle_connections in excess of `min_idle` are closed.
     idle_timeout: Option<Duration>,
     /// The duration to wait to start a connection before giving up.
-    connection_timeout: Duration,
+    connection_timeout: Option<Duration>,
+    /// The duration, if any, to wait for the manager to open a connection.
+    create_timeout: Option<Duration>,
+    /// The duration `Pool::healthcheck` waits for a usable connection.
+    healthcheck_timeout: Duration,
     /// The error sink.
-    error_sink: Box<ErrorSink<M::Error>>,
+    error_sink: Box<dyn ErrorSink<M::Error>>,
+    /// The connection customizer.
+    connection_customizer: Box<dyn ConnectionCustomizer<M::Connection, M::Error>>,
+    /// The executor background work is spawned on.
+    executor: Box<dyn Spawn>,
+    /// The clock used to track connection lifetimes.
+    clock: Box<dyn Clock>,
+    /// The observer notified of pool events.
+    observer: Box<dyn PoolObserver>,
     /// The time interval used to wake up and reap connections.
     reaper_rate: Duration,
+    /// The maximum random delay before the reaper first runs.
+    reaper_jitter: Duration,
+    /// Whether or not the reaper is spawned.
+    reaper: bool,
+    /// The number of times a failed connection attempt is retried.
+    connection_retries: u32,
+    /// The delay before the first retry of a failed connection attempt.
+    retry_base_delay: Duration,
+    /// The maximum delay between retries of a failed connection attempt.
+    retry_max_delay: Duration,
+    /// The consecutive connection failure threshold and cooldown, if any, of
+    /// the circuit breaker.
+    circuit_breaker: Option<(u32, Duration)>,
+    /// The maximum number of callers allowed to wait for a connection.
+    max_waiters: Option<u32>,
+    /// What checkouts do when no connection is available.
+    queue_strategy: QueueStrategy,
+    /// The duration, if any, after which idle connections are pinged.
+    keepalive_interval: Option<Duration>,
+    /// The duration, if any, for which a validation in the background stands
+    /// in for `test_on_check_out`.
+    background_validation: Option<Duration>,
+    /// The maximum number of connections, if any, opened at once to replenish the pool.
+    warmup_concurrency: Option<usize>,
+    /// The window and percentile of recent demand, if any, that the pool keeps
+    /// connections open for.
+    adaptive_idle: Option<(Duration, f64)>,
     _p: PhantomData<M>,
 }
 
 impl<M: ManageConnection> Default for Builder<M> {
     fn default() -> Self {
         Builder {
+            name: None,
             max_size: 10,
             min_idle: None,
+            min_connections: None,
+            lazy_min_idle: false,
             test_on_check_out: true,
+            selection_policy: Box::new(ConnectionOrder::Fifo),
+            test_on_return: false,
+            replace_broken: false,
             max_lifetime: Some(Duration::from_secs(30 * 60)),
+            expire_on_check_out: false,
+            max_uses: None,
+            leak_detection_threshold: None,
+            min_idle_alert_threshold: Duration::from_secs(60),
             idle_timeout: Some(Duration::from_secs(10 * 60)),
-            connection_timeout: Duration::from_secs(30),
+            connection_timeout: Some(Duration::from_secs(30)),
+            create_timeout: None,
+            healthcheck_timeout: Duration::from_secs(5),
             error_sink: Box::new(NopErrorSink),
+            connection_customizer: Box::new(NopConnectionCustomizer),
+            executor: Box::new(DefaultSpawn),
+            clock: Box::new(SystemClock),
+            observer: Box::new(NopPoolObserver),
             reaper_rate: Duration::from_secs(30),
+            reaper_jitter: Duration::from_secs(0),
+            reaper: true,
+            connection_retries: 0,
+            retry_base_delay: Duration::from_millis(100),
+            retry_max_delay: Duration::from_secs(10),
+            circuit_breaker: None,
+            max_waiters: None,
+            queue_strategy: QueueStrategy::Wait,
+            keepalive_interval: None,
+            background_validation: None,
+            warmup_concurrency: None,
+            adaptive_idle: None,
             _p: PhantomData,
         }
     }
@@ -203,11 +1056,28 @@ impl<M: ManageConnection> Builder<M> {
         Default::default()
     }
 
+    /// Sets a name identifying the pool, e.g. in traces and logs when a
+    /// program has several pools.
+    ///
+    /// The name is shown in the pool's `Debug` output and returned by
+    /// `Pool::name`. With the `tracing` feature, checkouts are traced in
+    /// `bb8.get` spans and opening connections in `bb8.connect` spans, and
+    /// both carry the name as their `pool` field, or the pool's address if it
+    /// has none. The spans also record how long they took as `elapsed_ms` and
+    /// how they ended as `outcome`, and checkouts record whether they were
+    /// served by an idle connection, a connection opened for them, or a
+    /// returned connection as `path`.
+    ///
+    /// Defaults to None.
+    pub fn name<S: Into<String>>(mut self, name: S) -> Builder<M> {
+        self.name = Some(name.into());
+        self
+    }
+
     /// Sets the maximum number of connections managed by the pool.
     ///
     /// Defaults to 10.
     pub fn max_size(mut self, max_size: u32) -> Builder<M> {
-        assert!(max_size > 0, "max_size must be greater than zero!");
         self.max_size = max_size;
         self
     }
@@ -223,6 +1093,35 @@ impl<M: ManageConnection> Builder<M> {
         self
     }
 
+    /// Sets the minimum connection count maintained by the pool.
+    ///
+    /// Unlike `min_idle`, this counts connections that are checked out as well
+    /// as idle ones. If set, the pool will open connections whenever it falls
+    /// below this many, and connections will not be reaped for exceeding
+    /// `idle_timeout` or `max_lifetime` if that would take the pool below it.
+    ///
+    /// It is an error for this to be larger than `max_size`. If the pool is
+    /// later shrunk with `Pool::set_max_size`, `max_size` takes precedence.
+    ///
+    /// Defaults to None.
+    pub fn min_connections(mut self, min_connections: Option<u32>) -> Builder<M> {
+        self.min_connections = min_connections;
+        self
+    }
+
+    /// If true, `min_idle` and `min_connections` are only maintained once a
+    /// checkout has succeeded, rather than from when the pool is built.
+    ///
+    /// `build` then opens no connections and resolves straight away, so a
+    /// process can start while the database isn't ready yet. The pool warms
+    /// up in the background after the first connection is handed out.
+    ///
+    /// Defaults to false.
+    pub fn lazy_min_idle(mut self, lazy_min_idle: bool) -> Builder<M> {
+        self.lazy_min_idle = lazy_min_idle;
+        self
+    }
+
     /// If true, the health of a connection will be verified through a call to
     /// `ManageConnection::is_valid` before it is provided to a pool user.
     ///
@@ -232,76 +1131,483 @@ impl<M: ManageConnection> Builder<M> {
         self
     }
 
-    /// Sets the maximum lifetime of connections in the pool.
-    ///
-    /// If set, connections will be closed at the next reaping after surviving
-    /// past this duration.
+    /// Sets the order in which idle connections are checked out.
     ///
-    /// If a connection reachs its maximum lifetime while checked out it will be
-    /// closed when it is returned to the pool.
+    /// This replaces any policy set with `selection_policy`.
     ///
-    /// Defaults to 30 minutes.
-    pub fn max_lifetime(mut self, max_lifetime: Option<Duration>) -> Builder<M> {
-        assert!(
-            max_lifetime != Some(Duration::from_secs(0)),
-            "max_lifetime must be greater than zero!"
-        );
-        self.max_lifetime = max_lifetime;
+    /// Defaults to `ConnectionOrder::Fifo`.
+    pub fn connection_order(mut self, connection_order: ConnectionOrder) -> Builder<M> {
+        self.selection_policy = Box::new(connection_order);
         self
     }
 
-    /// Sets the idle timeout used by the pool.
+    /// Sets the policy that picks which idle connection is checked out.
     ///
-    /// If set, idle connections in excess of `min_idle` will be closed at the
-    /// next reaping after remaining idle past this duration.
+    /// This replaces any order set with `connection_order`.
     ///
-    /// Defaults to 10 minutes.
-    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Builder<M> {
-        assert!(
-            idle_timeout != Some(Duration::from_secs(0)),
-            "idle_timeout must be greater than zero!"
-        );
-        self.idle_timeout = idle_timeout;
+    /// Defaults to `ConnectionOrder::Fifo`.
+    pub fn selection_policy(
+        mut self,
+        selection_policy: Box<dyn SelectionPolicy<M::Connection>>,
+    ) -> Builder<M> {
+        self.selection_policy = selection_policy;
         self
     }
 
-    /// Sets the connection timeout used by the pool.
+    /// If true, the health of a connection will be verified through a call to
+    /// `ManageConnection::is_valid` when it is returned to the pool, and it
+    /// will be closed instead of made idle if the check fails.
+    ///
+    /// Enabling this along with `test_on_check_out` is redundant.
+    ///
+    /// Defaults to false.
+    pub fn test_on_return(mut self, test_on_return: bool) -> Builder<M> {
+        self.test_on_return = test_on_return;
+        self
+    }
+
+    /// If true, a connection that is found to be broken as it's returned to
+    /// the pool, or fails `test_on_return`, is replaced straight away so the
+    /// next checkout doesn't have to wait for a new one to be opened.
+    ///
+    /// Otherwise a replacement is opened eagerly only as needed to maintain
+    /// `min_idle` and `min_connections`, and lazily by a later checkout
+    /// beyond that.
+    ///
+    /// Defaults to false.
+    pub fn replace_broken(mut self, replace_broken: bool) -> Builder<M> {
+        self.replace_broken = replace_broken;
+        self
+    }
+
+    /// Sets the maximum lifetime of connections in the pool.
+    ///
+    /// If set, connections will be closed at the next reaping after surviving
+    /// past this duration, see `reaper_rate`.
+    ///
+    /// If a connection reachs its maximum lifetime while checked out it will be
+    /// closed when it is returned to the pool.
+    ///
+    /// Defaults to 30 minutes.
+    pub fn max_lifetime(mut self, max_lifetime: Option<Duration>) -> Builder<M> {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    /// If true, an idle connection that has outlived `max_lifetime` is closed
+    /// rather than handed out when it would be checked out, instead of only
+    /// at the next reaping. The checkout then tries the next idle connection,
+    /// or opens a new one.
+    ///
+    /// Defaults to false.
+    pub fn expire_on_check_out(mut self, expire_on_check_out: bool) -> Builder<M> {
+        self.expire_on_check_out = expire_on_check_out;
+        self
+    }
+
+    /// Sets the maximum number of times each connection in the pool can be
+    /// used.
+    ///
+    /// If set, connections will be closed when they are returned to the pool
+    /// after being checked out this many times.
+    ///
+    /// Defaults to None.
+    pub fn max_uses(mut self, max_uses: Option<u64>) -> Builder<M> {
+        self.max_uses = max_uses;
+        self
+    }
+
+    /// Sets the leak detection threshold used by the pool.
+    ///
+    /// If set, connections that have been checked out for longer than this
+    /// are reported to the error sink through `ErrorSink::sink_leak` at the
+    /// next reaping. Each connection is reported once per checkout, and is
+    /// not reclaimed from the user holding it.
+    ///
+    /// Defaults to None.
+    pub fn leak_detection_threshold(
+        mut self,
+        leak_detection_threshold: Option<Duration>,
+    ) -> Builder<M> {
+        self.leak_detection_threshold = leak_detection_threshold;
+        self
+    }
+
+    /// Sets how long the pool can fail to maintain `min_idle` before this is
+    /// reported.
+    ///
+    /// The pool is failing to maintain `min_idle` while it has fewer idle
+    /// connections than that but could open more without exceeding
+    /// `max_size`, e.g. because the database is refusing connections. Once
+    /// the reaper finds this has lasted for at least this long, it calls
+    /// `PoolObserver::on_min_idle_unmet`, once until the pool recovers. This
+    /// tells a sustained outage apart from a transient blip, whose errors only
+    /// reach the error sink.
+    ///
+    /// Defaults to 1 minute.
+    pub fn min_idle_alert_threshold(mut self, min_idle_alert_threshold: Duration) -> Builder<M> {
+        self.min_idle_alert_threshold = min_idle_alert_threshold;
+        self
+    }
+
+    /// Sets the idle timeout used by the pool.
+    ///
+    /// If set, idle connections in excess of `min_idle` will be closed at the
+    /// next reaping after remaining idle past this duration, see
+    /// `reaper_rate`.
+    ///
+    /// Defaults to 10 minutes.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Builder<M> {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets the connection timeout used by the pool.
     ///
     /// Futures returned by `Pool::get` will wait this long before giving up and
-    /// resolving with an error.
+    /// resolving with an error. This bounds the whole checkout, including
+    /// validating idle connections with `test_on_check_out`. Individual calls
+    /// can override this through `Pool::get_timeout` and `Pool::run_timeout`.
+    ///
+    /// This takes either a `Duration` or an `Option<Duration>`. With `None`,
+    /// checkouts wait for a connection indefinitely, as does opening a
+    /// dedicated connection. Use this with care: if the connections are all
+    /// held by tasks that are themselves waiting on a checkout, e.g. a task that
+    /// checks out a second connection while holding one, those tasks deadlock
+    /// instead of failing with `RunError::TimedOut`. Likewise a database that
+    /// stops accepting connections stalls checkouts forever.
     ///
     /// Defaults to 30 seconds.
-    pub fn connection_timeout(mut self, connection_timeout: Duration) -> Builder<M> {
-        assert!(
-            connection_timeout > Duration::from_secs(0),
-            "connection_timeout must be non-zero"
-        );
+    pub fn connection_timeout<T>(mut self, connection_timeout: T) -> Builder<M>
+    where
+        T: Into<Option<Duration>>,
+    {
+        let connection_timeout = connection_timeout.into();
         self.connection_timeout = connection_timeout;
         self
     }
 
+    /// Sets the create timeout used by the pool.
+    ///
+    /// If set, attempts to open a connection through
+    /// `ManageConnection::connect` that take longer than this are abandoned,
+    /// and reported to the error sink through `ErrorSink::sink_create_timeout`.
+    /// A timed out attempt counts as a failure for the purposes of
+    /// `connection_retry` and `circuit_breaker`.
+    ///
+    /// Unlike `connection_timeout`, this bounds opening a connection rather
+    /// than a checkout, so that a connection attempt that hangs doesn't hold
+    /// one of the pool's slots forever.
+    ///
+    /// Defaults to None.
+    pub fn create_timeout(mut self, create_timeout: Option<Duration>) -> Builder<M> {
+        self.create_timeout = create_timeout;
+        self
+    }
+
+    /// Sets how long `Pool::healthcheck` waits for a usable connection before
+    /// reporting the pool as unhealthy.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn healthcheck_timeout(mut self, healthcheck_timeout: Duration) -> Builder<M> {
+        self.healthcheck_timeout = healthcheck_timeout;
+        self
+    }
+
     /// Set the sink for errors that are not associated with any particular operation
     /// on the pool. This can be used to log and monitor failures.
     ///
     /// Defaults to `NopErrorSink`.
-    pub fn error_sink(mut self, error_sink: Box<ErrorSink<M::Error>>) -> Builder<M> {
+    pub fn error_sink(mut self, error_sink: Box<dyn ErrorSink<M::Error>>) -> Builder<M> {
         self.error_sink = error_sink;
         self
     }
 
-    /// Used by tests
-    #[allow(dead_code)]
+    /// Set the connection customizer, which is called as connections are
+    /// checked out of and returned to the pool.
+    ///
+    /// Defaults to `NopConnectionCustomizer`.
+    pub fn connection_customizer(
+        mut self,
+        connection_customizer: Box<dyn ConnectionCustomizer<M::Connection, M::Error>>,
+    ) -> Builder<M> {
+        self.connection_customizer = connection_customizer;
+        self
+    }
+
+    /// Set the executor that the pool spawns its background work on.
+    ///
+    /// Defaults to `DefaultSpawn`, which requires the pool to be used from
+    /// within a tokio runtime.
+    pub fn executor(mut self, executor: Box<dyn Spawn>) -> Builder<M> {
+        self.executor = executor;
+        self
+    }
+
+    /// Set the clock the pool uses to track connection lifetimes, idle
+    /// durations and checkout durations.
+    ///
+    /// Timeouts are still driven by the tokio timer.
+    ///
+    /// Defaults to `SystemClock`.
+    pub fn clock(mut self, clock: Box<dyn Clock>) -> Builder<M> {
+        self.clock = clock;
+        self
+    }
+
+    /// Set the observer that is notified of events in the pool.
+    ///
+    /// Defaults to `NopPoolObserver`.
+    pub fn observer(mut self, observer: Box<dyn PoolObserver>) -> Builder<M> {
+        self.observer = observer;
+        self
+    }
+
+    /// Sets how failed attempts to open a connection are retried.
+    ///
+    /// A failed attempt is retried up to `max_retries` times before the error
+    /// is reported. The delay before each retry starts at `base_delay` and
+    /// doubles with every attempt, up to `max_delay`, with random jitter
+    /// applied so that many connections don't retry in lockstep. Errors from
+    /// attempts that are retried are sent to the error sink.
+    ///
+    /// Defaults to no retries.
+    pub fn connection_retry(
+        mut self,
+        max_retries: u32,
+        base_delay: Duration,
+        max_delay: Duration,
+    ) -> Builder<M> {
+        self.connection_retries = max_retries;
+        self.retry_base_delay = base_delay;
+        self.retry_max_delay = max_delay;
+        self
+    }
+
+    /// Enables a circuit breaker that fails checkouts fast while the database
+    /// is unreachable.
+    ///
+    /// Once `failure_threshold` consecutive attempts to open a connection
+    /// have failed, checkouts that would have to wait on a new connection
+    /// resolve immediately with `RunError::CircuitOpen` instead. Idle
+    /// connections are still handed out. After `cooldown` has elapsed a
+    /// single checkout is let through to probe the database, and the first
+    /// successful connection closes the circuit again.
+    ///
+    /// Defaults to None.
+    pub fn circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Builder<M> {
+        self.circuit_breaker = Some((failure_threshold, cooldown));
+        self
+    }
+
+    /// Sets the maximum number of callers allowed to wait for a connection.
+    ///
+    /// If set, checkouts that would have to wait for a connection while this
+    /// many other callers are already waiting resolve immediately with
+    /// `RunError::QueueFull`.
+    ///
+    /// Defaults to None, meaning no limit.
+    pub fn max_waiters(mut self, max_waiters: Option<u32>) -> Builder<M> {
+        self.max_waiters = max_waiters;
+        self
+    }
+
+    /// Sets what checkouts do when every connection is checked out and the
+    /// pool is already at `max_size`.
+    ///
+    /// Checkouts that can open a new connection always wait for it, bounded
+    /// by the connection timeout.
+    ///
+    /// Defaults to `QueueStrategy::Wait`.
+    pub fn queue_strategy(mut self, queue_strategy: QueueStrategy) -> Builder<M> {
+        self.queue_strategy = queue_strategy;
+        self
+    }
+
+    /// Sets the keepalive interval used by the pool.
+    ///
+    /// If set, connections that have sat idle for longer than this are
+    /// checked with `ManageConnection::is_valid` at the next reaping, which
+    /// keeps them from being silently dropped by firewalls and detects dead
+    /// connections before they are checked out. Connections that fail the
+    /// check are closed and replaced as needed.
+    ///
+    /// Defaults to None.
+    pub fn keepalive_interval(mut self, keepalive_interval: Option<Duration>) -> Builder<M> {
+        self.keepalive_interval = keepalive_interval;
+        self
+    }
+
+    /// Sets the background validation interval used by the pool.
+    ///
+    /// If set, idle connections that haven't been validated for this long are
+    /// checked with `ManageConnection::is_valid` at the next reaping, and
+    /// `test_on_check_out` is skipped for connections that were validated, or
+    /// opened, within it. This takes validation off the checkout path at the
+    /// cost of more background work, at the risk of handing out a connection
+    /// that broke since it was last validated. Connections that are returned
+    /// to the pool have to be validated again, unless `test_on_return` is set.
+    ///
+    /// Defaults to None.
+    pub fn background_validation(mut self, background_validation: Option<Duration>) -> Builder<M> {
+        self.background_validation = background_validation;
+        self
+    }
+
+    /// Sets the maximum number of connections opened at once when the pool
+    /// replenishes itself to `min_idle` or `min_connections`.
+    ///
+    /// This applies when the pool is built and when connections are replaced
+    /// afterwards, e.g. after reaping, so that a large minimum doesn't flood a
+    /// database that limits the rate of new connections. Connections opened
+    /// for checkouts waiting on one are not limited.
+    ///
+    /// Defaults to no limit.
+    pub fn warmup_concurrency(mut self, warmup_concurrency: usize) -> Builder<M> {
+        self.warmup_concurrency = Some(warmup_concurrency);
+        self
+    }
+
+    /// Sets how often the reaper runs.
+    ///
+    /// The reaper closes connections that have exceeded `idle_timeout` or
+    /// `max_lifetime`, pings connections for `keepalive_interval` and
+    /// `background_validation`, and reports leaks for
+    /// `leak_detection_threshold`. Since these are only acted on when it
+    /// runs, a connection may live for up to `reaper_rate` longer than its
+    /// timeout, so a shorter rate suits aggressive timeouts.
+    ///
+    /// Defaults to 30 seconds.
     pub fn reaper_rate(mut self, reaper_rate: Duration) -> Builder<M> {
         self.reaper_rate = reaper_rate;
         self
     }
 
+    /// Sets the maximum random delay added before the reaper first runs.
+    ///
+    /// Pools built at the same time with the same `reaper_rate` otherwise
+    /// reap in lockstep, all validating and opening connections at once. A
+    /// jitter offsets each pool's reaper by a random amount up to this, which
+    /// spreads their work out.
+    ///
+    /// Defaults to zero.
+    pub fn reaper_jitter(mut self, reaper_jitter: Duration) -> Builder<M> {
+        self.reaper_jitter = reaper_jitter;
+        self
+    }
+
+    /// Keeps enough connections open for recent demand.
+    ///
+    /// Each time the reaper runs, it records the peak number of concurrent
+    /// checkouts, counting those waiting for a connection, since it last ran.
+    /// The pool then keeps open at least the `target_percentile` (from 0 to
+    /// 100) of the peaks recorded over the last `window`, bounded by
+    /// `max_size`: missing connections are opened as for `min_idle`, and idle
+    /// connections aren't reaped for `idle_timeout` or `max_lifetime` below
+    /// it. This keeps the pool warm ahead of ramps in demand while letting it
+    /// shrink off-peak, on top of any `min_idle` and `min_connections`.
+    ///
+    /// The target is only recomputed by the reaper, so the `window` should
+    /// span several `reaper_rate`s and this has no effect if the reaper is
+    /// disabled.
+    ///
+    /// Defaults to None.
+    pub fn adaptive_idle(mut self, window: Duration, target_percentile: f64) -> Builder<M> {
+        self.adaptive_idle = Some((window, target_percentile));
+        self
+    }
+
+    /// Sets whether the pool spawns the reaper.
+    ///
+    /// The reaper is a background task that holds a timer for as long as the
+    /// pool exists. When it is disabled, `idle_timeout`, `max_lifetime`,
+    /// `keepalive_interval`, `background_validation`,
+    /// `leak_detection_threshold`, `adaptive_idle` and
+    /// `min_idle_alert_threshold` are not acted on in the background, which
+    /// suits short-lived pools.
+    ///
+    /// Defaults to true.
+    pub fn reaper(mut self, reaper: bool) -> Builder<M> {
+        self.reaper = reaper;
+        self
+    }
+
+    // The delay before retrying a failed connection attempt for the
+    // `attempt + 1`th time.
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .retry_base_delay
+            .checked_mul(1 << min(attempt, 31))
+            .map_or(self.retry_max_delay, |d| min(d, self.retry_max_delay));
+        delay / 2 + random_duration(delay / 2)
+    }
+
+    /// Checks the configuration for settings that are out of range or
+    /// inconsistent with each other, e.g. a `min_idle` larger than `max_size`
+    /// or a zero timeout, returning the first problem found.
+    ///
+    /// The setters don't check their arguments, so e.g. a configuration loaded
+    /// from a file can be reported on gracefully. `build`, `build_with_result`
+    /// and `build_unchecked` panic with the error if the configuration is
+    /// invalid.
+    pub fn validate(&self) -> Result<(), BuilderError> {
+        if self.max_size == 0 {
+            return Err(BuilderError::Zero("max_size"));
+        }
+        if self
+            .min_idle
+            .is_some_and(|min_idle| min_idle > self.max_size)
+        {
+            return Err(BuilderError::ExceedsMaxSize("min_idle"));
+        }
+        if self
+            .min_connections
+            .is_some_and(|min_connections| min_connections > self.max_size)
+        {
+            return Err(BuilderError::ExceedsMaxSize("min_connections"));
+        }
+        let durations = [
+            ("max_lifetime", self.max_lifetime),
+            ("leak_detection_threshold", self.leak_detection_threshold),
+            ("idle_timeout", self.idle_timeout),
+            ("connection_timeout", self.connection_timeout),
+            ("create_timeout", self.create_timeout),
+            ("healthcheck_timeout", Some(self.healthcheck_timeout)),
+            ("keepalive_interval", self.keepalive_interval),
+            ("background_validation", self.background_validation),
+            ("reaper_rate", Some(self.reaper_rate)),
+        ];
+        for &(setting, duration) in &durations {
+            if duration == Some(Duration::from_secs(0)) {
+                return Err(BuilderError::Zero(setting));
+            }
+        }
+        if self.max_uses == Some(0) {
+            return Err(BuilderError::Zero("max_uses"));
+        }
+        if self.warmup_concurrency == Some(0) {
+            return Err(BuilderError::Zero("warmup_concurrency"));
+        }
+        if self
+            .circuit_breaker
+            .is_some_and(|(threshold, _)| threshold == 0)
+        {
+            return Err(BuilderError::Zero("failure_threshold"));
+        }
+        if self.retry_base_delay > self.retry_max_delay {
+            return Err(BuilderError::RetryDelay);
+        }
+        if let Some((_, target_percentile)) = self.adaptive_idle {
+            if !(0.0..=100.0).contains(&target_percentile) {
+                return Err(BuilderError::TargetPercentile(target_percentile));
+            }
+        }
+        Ok(())
+    }
+
     fn build_inner(self, manager: M) -> (Pool<M>, impl Future<Item = (), Error = M::Error> + Send) {
-        if let Some(min_idle) = self.min_idle {
-            assert!(
-                self.max_size >= min_idle,
-                "min_idle must be no larger than max_size"
-            );
+        if let Err(e) = self.validate() {
+            panic!("{}", e);
         }
 
         let p = Pool::new_inner(self, manager);
@@ -312,19 +1618,63 @@ impl<M: ManageConnection> Builder<M> {
     /// Consumes the builder, returning a new, initialized `Pool`.
     ///
     /// The `Pool` will not be returned until it has established its configured
-    /// minimum number of connections, or it times out.
+    /// minimum number of connections, or it times out. If neither `min_idle`
+    /// nor `min_connections` is set, or `lazy_min_idle` is, no connections are
+    /// opened until the pool is first used, and the `Pool` is returned
+    /// immediately.
     pub fn build(self, manager: M) -> impl Future<Item = Pool<M>, Error = M::Error> + Send {
         let (p, f) = self.build_inner(manager);
         f.map(|_| p)
     }
 
+    /// Consumes the builder, returning a new `Pool` once it has established
+    /// its configured minimum number of connections, or has tried to for
+    /// `connection_timeout`.
+    ///
+    /// Unlike `build`, the pool is returned even if it couldn't open all of
+    /// them, along with a `PartialInit` describing the shortfall. The missing
+    /// connections are opened as the pool is used. The returned future never
+    /// fails.
+    pub fn build_with_result(
+        self,
+        manager: M,
+    ) -> impl Future<Item = (Pool<M>, Result<(), PartialInit<M::Error>>), Error = ()> + Send {
+        let timeout = self.connection_timeout;
+        let wanted = min(
+            max(
+                self.min_idle.unwrap_or(0),
+                self.min_connections.unwrap_or(0),
+            ),
+            self.max_size,
+        );
+        let (p, f) = self.build_inner(manager);
+        maybe_timeout(f, timeout).then(move |r| {
+            let error = match r {
+                Ok(()) => None,
+                Err(e) => e.into_inner(),
+            };
+            let connections = p.state().connections;
+            let result = if connections >= wanted {
+                Ok(())
+            } else {
+                Err(PartialInit {
+                    connections,
+                    wanted,
+                    error,
+                })
+            };
+            Ok((p, result))
+        })
+    }
+
     /// Consumes the builder, returning a new, initialized `Pool`.
     ///
     /// Unlike `build`, this does not wait for any connections to be established
-    /// before returning.
+    /// before returning. The configured minimum number of connections are
+    /// opened in the background.
     pub fn build_unchecked(self, manager: M) -> Pool<M> {
         let (p, f) = self.build_inner(manager);
-        p.spawn(p.sink_error(f));
+        p.spawn(p.sink_error(ErrorContext::Connect, f));
         p
     }
 }
@@ -335,19 +1685,274 @@ struct PoolInternals<C>
 where
     C: Send,
 {
-    waiters: VecDeque<oneshot::Sender<Conn<C>>>,
+    waiters: Waiters<C>,
     conns: VecDeque<IdleConn<C>>,
     num_conns: u32,
     pending_conns: u32,
+    max_size: u32,
+    min_idle: Option<u32>,
+    generation: u64,
+    stats: Statistics,
+    circuit: CircuitBreaker,
+    closed: bool,
+    next_id: u64,
+    // Connections that are checked out, by id, when leak detection is enabled.
+    checkouts: HashMap<u64, Checkout>,
+    // Connections parked with `PooledConnection::park`, by id.
+    parked: HashMap<u64, Conn<C>>,
+    // Callers of `Pool::wait_for_idle`, with the idle count they're waiting for.
+    idle_waiters: Vec<(u32, oneshot::Sender<()>)>,
+    // Callers of `Pool::drain`, waiting for every connection to be closed.
+    drain_waiters: Vec<oneshot::Sender<()>>,
+    // The total number of slots reserved by `Pool::with_reservation` handles.
+    reservations: u32,
+    // The reserved slots that aren't used by a checkout, which other checkouts
+    // must leave available.
+    reserved: u32,
+    // The peak demand since the reaper last sampled it.
+    peak_demand: u32,
+    // The peak demand of each reaper run within the `adaptive_idle` window.
+    demand_samples: VecDeque<(Instant, u32)>,
+    // The number of connections `adaptive_idle` keeps open.
+    adaptive_target: u32,
+    // Since when the pool has been failing to maintain `min_idle`, and whether
+    // that has been reported.
+    min_idle_unmet_since: Option<Instant>,
+    min_idle_unmet_reported: bool,
+}
+
+/// Checkouts waiting on a connection, queued by priority.
+struct Waiters<C>
+where
+    C: Send,
+{
+    // Indexed by `Priority`, lowest first.
+    queues: [VecDeque<oneshot::Sender<Conn<C>>>; 3],
+    // Checkouts using a reserved slot, which are served before any others.
+    reserved: VecDeque<oneshot::Sender<Conn<C>>>,
+    // The number of checkouts in the queues that have given up waiting. They
+    // are skipped as they reach the front, and swept out once they make up
+    // half of the queues, so giving up and serving are O(1) amortized.
+    canceled: usize,
+}
+
+impl<C> Waiters<C>
+where
+    C: Send,
+{
+    fn new() -> Waiters<C> {
+        Waiters {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            reserved: VecDeque::new(),
+            canceled: 0,
+        }
+    }
+
+    // The number of checkouts still waiting.
+    fn len(&self) -> usize {
+        self.queued().saturating_sub(self.canceled)
+    }
+
+    fn queued(&self) -> usize {
+        self.reserved.len() + self.queues.iter().map(VecDeque::len).sum::<usize>()
+    }
+
+    fn push(&mut self, priority: Priority, waiter: oneshot::Sender<Conn<C>>) {
+        self.queues[priority as usize].push_back(waiter);
+    }
+
+    fn push_reserved(&mut self, waiter: oneshot::Sender<Conn<C>>) {
+        self.reserved.push_back(waiter);
+    }
+
+    // The longest waiting checkout using a reserved slot, or otherwise, if
+    // `unreserved` allows it, the longest waiting checkout of the highest
+    // priority.
+    fn pop(&mut self, unreserved: bool) -> Option<oneshot::Sender<Conn<C>>> {
+        let Waiters {
+            queues,
+            reserved,
+            canceled,
+        } = self;
+        pop_waiting(reserved, canceled).or_else(|| {
+            if !unreserved {
+                return None;
+            }
+            queues
+                .iter_mut()
+                .rev()
+                .find_map(|queue| pop_waiting(queue, canceled))
+        })
+    }
+
+    // Records that a queued checkout has given up waiting.
+    fn cancel(&mut self) {
+        self.canceled += 1;
+        if self.canceled * 2 > self.queued() {
+            self.remove_canceled();
+        }
+    }
+
+    // Forget checkouts that have given up waiting.
+    fn remove_canceled(&mut self) {
+        self.reserved.retain(|waiter| !waiter.is_canceled());
+        for queue in &mut self.queues {
+            queue.retain(|waiter| !waiter.is_canceled());
+        }
+        self.canceled = 0;
+    }
+
+    fn clear(&mut self) {
+        self.reserved.clear();
+        for queue in &mut self.queues {
+            queue.clear();
+        }
+        self.canceled = 0;
+    }
+}
+
+// The checkout at the front of `queue` that is still waiting, dropping those
+// that have given up before it.
+fn pop_waiting<C>(
+    queue: &mut VecDeque<oneshot::Sender<Conn<C>>>,
+    canceled: &mut usize,
+) -> Option<oneshot::Sender<Conn<C>>>
+where
+    C: Send,
+{
+    while let Some(waiter) = queue.pop_front() {
+        if !waiter.is_canceled() {
+            return Some(waiter);
+        }
+        *canceled = canceled.saturating_sub(1);
+    }
+    None
+}
+
+/// When a connection was checked out, and whether it has been reported as
+/// leaked yet.
+#[derive(Debug)]
+struct Checkout {
+    since: Instant,
+    reported: bool,
+}
+
+/// Tracks consecutive connection failures to decide when to stop waiting on
+/// new connections.
+#[derive(Debug, Default)]
+struct CircuitBreaker {
+    failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    // Decide whether a checkout that has to wait on a new connection should
+    // fail immediately. Once the cooldown has elapsed a single checkout that
+    // opens a connection is let through as a probe.
+    fn is_open(&mut self, cooldown: Duration, will_connect: bool) -> bool {
+        match self.opened_at {
+            None => false,
+            Some(opened_at) if opened_at.elapsed() < cooldown || self.probing => true,
+            Some(_) => {
+                self.probing = will_connect;
+                false
+            }
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.failures = 0;
+        self.opened_at = None;
+        self.probing = false;
+    }
+
+    fn record_failure(&mut self, threshold: Option<u32>) {
+        self.failures += 1;
+        self.probing = false;
+        if threshold.is_some_and(|threshold| self.failures >= threshold) {
+            self.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// The number of buckets in the histogram returned by `Pool::latency_histogram`.
+#[cfg(feature = "histogram")]
+pub const LATENCY_BUCKETS: usize = 14;
+
+// The inclusive upper bound of each latency histogram bucket.
+#[cfg(feature = "histogram")]
+const LATENCY_BOUNDS: [Duration; LATENCY_BUCKETS] = [
+    Duration::from_millis(1),
+    Duration::from_millis(2),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+    Duration::MAX,
+];
+
+/// Counts of checkout wait times, bucketed by `LATENCY_BOUNDS`.
+#[cfg(feature = "histogram")]
+#[derive(Debug, Default)]
+struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+}
+
+#[cfg(feature = "histogram")]
+impl LatencyHistogram {
+    fn record(&self, wait: Duration) {
+        let bucket = LATENCY_BOUNDS
+            .iter()
+            .position(|bound| wait <= *bound)
+            .unwrap_or(LATENCY_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Cumulative counters describing the pool's history.
+#[derive(Debug, Default)]
+struct Statistics {
+    wait_count: u64,
+    total_wait: Duration,
+    max_wait: Duration,
+    connections_created: u64,
+    connection_errors: u64,
+    connections_reaped: u64,
+}
+
+impl Statistics {
+    fn record_wait(&mut self, wait: Duration) {
+        self.wait_count += 1;
+        self.total_wait += wait;
+        self.max_wait = max(self.max_wait, wait);
+    }
 }
 
 impl<C> PoolInternals<C>
 where
     C: Send,
 {
+    // Checkouts waiting on a connection are always served before it is made
+    // idle, highest priority first and otherwise in the order they started
+    // waiting.
     fn put_idle_conn(&mut self, mut conn: IdleConn<C>) {
         loop {
-            if let Some(waiter) = self.waiters.pop_front() {
+            let unreserved = self.can_serve_unreserved(0);
+            if let Some(waiter) = self.waiters.pop(unreserved) {
                 // This connection is no longer idle, send it back out.
                 match waiter.send(conn.conn) {
                     Ok(_) => break,
@@ -357,14 +1962,135 @@ where
             } else {
                 // Queue it in the idle queue.
                 self.conns.push_back(conn);
+                self.notify_idle_waiters();
                 break;
             }
         }
     }
+
+    // Whether a checkout that doesn't use a reserved slot may take `taking`
+    // more connections than are already checked out, while leaving enough
+    // capacity for the unused reserved slots.
+    fn can_serve_unreserved(&self, taking: u32) -> bool {
+        let checked_out = self.num_conns.saturating_sub(self.conns.len() as u32);
+        self.reserved == 0 || checked_out + taking + self.reserved <= self.max_size
+    }
+
+    // The number of checkouts either holding or waiting for a connection.
+    fn demand(&self) -> u32 {
+        self.num_conns.saturating_sub(self.conns.len() as u32) + self.waiters.len() as u32
+    }
+
+    // Wake the `wait_for_idle` callers whose idle count has been reached.
+    fn notify_idle_waiters(&mut self) {
+        if self.idle_waiters.is_empty() {
+            return;
+        }
+        let idle = self.conns.len() as u32;
+        let (ready, waiting): (Vec<_>, _) = self
+            .idle_waiters
+            .drain(..)
+            .partition2(|&(min, _)| idle >= min);
+        self.idle_waiters = waiting;
+        for (_, waiter) in ready {
+            let _ = waiter.send(());
+        }
+    }
 }
 
-/// The guts of a `Pool`.
-#[allow(missing_debug_implementations)]
+/// Copies of the values reported by `Pool::state`, so that they can be read
+/// without taking the pool lock.
+#[derive(Debug, Default)]
+struct Counters {
+    connections: AtomicU32,
+    idle_connections: AtomicU32,
+    pending_connections: AtomicU32,
+    waiters: AtomicU32,
+    wait_count: AtomicU64,
+    total_wait_nanos: AtomicU64,
+    max_wait_nanos: AtomicU64,
+    connections_created: AtomicU64,
+    connection_errors: AtomicU64,
+    connections_reaped: AtomicU64,
+}
+
+impl Counters {
+    fn publish<C: Send>(&self, internals: &PoolInternals<C>) {
+        self.connections
+            .store(internals.num_conns, Ordering::Relaxed);
+        self.idle_connections
+            .store(internals.conns.len() as u32, Ordering::Relaxed);
+        self.pending_connections
+            .store(internals.pending_conns, Ordering::Relaxed);
+        self.waiters
+            .store(internals.waiters.len() as u32, Ordering::Relaxed);
+        self.wait_count
+            .store(internals.stats.wait_count, Ordering::Relaxed);
+        self.total_wait_nanos.store(
+            duration_nanos(internals.stats.total_wait),
+            Ordering::Relaxed,
+        );
+        self.max_wait_nanos
+            .store(duration_nanos(internals.stats.max_wait), Ordering::Relaxed);
+        self.connections_created
+            .store(internals.stats.connections_created, Ordering::Relaxed);
+        self.connection_errors
+            .store(internals.stats.connection_errors, Ordering::Relaxed);
+        self.connections_reaped
+            .store(internals.stats.connections_reaped, Ordering::Relaxed);
+    }
+}
+
+fn duration_nanos(d: Duration) -> u64 {
+    min(d.as_nanos(), u128::from(u64::MAX)) as u64
+}
+
+/// The pool lock. Releasing it publishes the pool's counters.
+struct InternalsGuard<'a, C>
+where
+    C: Send + 'a,
+{
+    guard: MutexGuard<'a, PoolInternals<C>>,
+    counters: &'a Counters,
+    #[cfg(feature = "metrics")]
+    gauges: &'a pool_metrics::Gauges,
+}
+
+impl<'a, C> Deref for InternalsGuard<'a, C>
+where
+    C: Send,
+{
+    type Target = PoolInternals<C>;
+
+    fn deref(&self) -> &PoolInternals<C> {
+        &self.guard
+    }
+}
+
+impl<'a, C> DerefMut for InternalsGuard<'a, C>
+where
+    C: Send,
+{
+    fn deref_mut(&mut self) -> &mut PoolInternals<C> {
+        &mut self.guard
+    }
+}
+
+impl<'a, C> Drop for InternalsGuard<'a, C>
+where
+    C: Send,
+{
+    fn drop(&mut self) {
+        let demand = self.guard.demand();
+        self.guard.peak_demand = max(self.guard.peak_demand, demand);
+        self.counters.publish(&self.guard);
+        #[cfg(feature = "metrics")]
+        self.gauges.publish(&self.guard);
+    }
+}
+
+/// The guts of a `Pool`.
+#[allow(missing_debug_implementations)]
 struct SharedPool<M>
 where
     M: ManageConnection + Send,
@@ -372,32 +2098,73 @@ where
     statics: Builder<M>,
     manager: M,
     internals: Mutex<PoolInternals<M::Connection>>,
+    counters: Counters,
+    #[cfg(feature = "metrics")]
+    gauges: pool_metrics::Gauges,
+    #[cfg(feature = "histogram")]
+    histogram: LatencyHistogram,
+    // Whether `min_idle` and `min_connections` are maintained yet, which with
+    // `lazy_min_idle` is only once a checkout has succeeded.
+    warmed: AtomicBool,
+}
+
+impl<M> Drop for SharedPool<M>
+where
+    M: ManageConnection + Send,
+{
+    fn drop(&mut self) {
+        let internals = self
+            .internals
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let idle = internals.conns.drain(..).map(|idle| idle.conn.conn);
+        let parked = internals.parked.drain().map(|(_, conn)| conn.conn);
+        for conn in idle.chain(parked) {
+            self.manager.on_pool_drop(conn);
+        }
+    }
 }
 
 impl<M> SharedPool<M>
 where
     M: ManageConnection,
 {
+    fn lock(&self) -> InternalsGuard<'_, M::Connection> {
+        InternalsGuard {
+            guard: self.internals.lock().unwrap(),
+            counters: &self.counters,
+            #[cfg(feature = "metrics")]
+            gauges: &self.gauges,
+        }
+    }
+
     fn spawn<R>(&self, runnable: R)
     where
         R: IntoFuture<Item = (), Error = ()>,
         R::Future: Send + 'static,
     {
-        spawn(runnable.into_future());
+        self.statics
+            .executor
+            .spawn(Box::new(runnable.into_future()));
     }
 
-    fn sink_error<'a, E, F>(&self, f: F) -> impl Future<Item = F::Item, Error = ()> + Send + 'a
+    fn sink_error<'a, E, F>(
+        &self,
+        context: ErrorContext,
+        f: F,
+    ) -> impl Future<Item = F::Item, Error = ()> + Send + 'a
     where
         F: Future<Error = E> + Send + 'a,
         E: Into<M::Error>,
     {
         let sink = self.statics.error_sink.boxed_clone();
-        f.map_err(move |e| sink.sink(e.into()))
+        f.map_err(move |e| sink.sink_with_context(e.into(), context))
     }
 
     fn or_timeout<'a, F>(
         &self,
         f: F,
+        timeout: Option<Duration>,
     ) -> impl Future<Item = Option<F::Item>, Error = F::Error> + Send + 'a
     where
         F: IntoFuture + Send,
@@ -406,7 +2173,7 @@ where
         F::Error: Send + ::std::fmt::Debug + 'a,
     {
         let runnable = f.into_future();
-        Timeout::new(runnable, self.statics.connection_timeout).then(|r| match r {
+        maybe_timeout(runnable, timeout).then(|r| match r {
             Ok(item) => Ok(Some(item)),
             Err(ref e) if e.is_elapsed() || e.is_timer() => Ok(None),
             Err(e) => Err(e.into_inner().unwrap()),
@@ -414,12 +2181,70 @@ where
     }
 }
 
+/// A handle to a `Pool` that doesn't keep it alive, created with
+/// `Pool::downgrade`.
+pub struct WeakPool<M>
+where
+    M: ManageConnection,
+{
+    inner: Weak<SharedPool<M>>,
+    reservation: Option<Weak<Reservation<M>>>,
+    connection_timeout: Option<Duration>,
+}
+
+impl<M> WeakPool<M>
+where
+    M: ManageConnection,
+{
+    /// Returns a handle to the pool, the same as the one this was created
+    /// from, if the pool hasn't been dropped yet.
+    ///
+    /// If this was created from a `Pool::with_reservation` handle, the
+    /// reservation must still be held by another handle too.
+    pub fn upgrade(&self) -> Option<Pool<M>> {
+        let reservation = match self.reservation {
+            Some(ref reservation) => Some(reservation.upgrade()?),
+            None => None,
+        };
+        Some(Pool {
+            inner: self.inner.upgrade()?,
+            reservation,
+            connection_timeout: self.connection_timeout,
+        })
+    }
+}
+
+impl<M> Clone for WeakPool<M>
+where
+    M: ManageConnection,
+{
+    fn clone(&self) -> Self {
+        WeakPool {
+            inner: self.inner.clone(),
+            reservation: self.reservation.clone(),
+            connection_timeout: self.connection_timeout,
+        }
+    }
+}
+
+impl<M> fmt::Debug for WeakPool<M>
+where
+    M: ManageConnection,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_fmt(format_args!("WeakPool({:p})", self.inner.as_ptr()))
+    }
+}
+
 /// A generic connection pool.
 pub struct Pool<M>
 where
     M: ManageConnection,
 {
     inner: Arc<SharedPool<M>>,
+    reservation: Option<Arc<Reservation<M>>>,
+    // The connection timeout of checkouts made through this handle.
+    connection_timeout: Option<Duration>,
 }
 
 impl<M> Clone for Pool<M>
@@ -429,6 +2254,8 @@ where
     fn clone(&self) -> Self {
         Pool {
             inner: self.inner.clone(),
+            reservation: self.reservation.clone(),
+            connection_timeout: self.connection_timeout,
         }
     }
 }
@@ -438,7 +2265,10 @@ where
     M: ManageConnection,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_fmt(format_args!("Pool({:p})", self.inner))
+        match self.inner.statics.name {
+            Some(ref name) => f.write_fmt(format_args!("Pool({:?}, {:p})", name, self.inner)),
+            None => f.write_fmt(format_args!("Pool({:p})", self.inner)),
+        }
     }
 }
 
@@ -451,94 +2281,298 @@ fn add_connection<M>(
 where
     M: ManageConnection,
 {
-    assert!(internals.num_conns + internals.pending_conns < pool.statics.max_size);
+    assert!(internals.num_conns + internals.pending_conns < internals.max_size);
     internals.pending_conns += 1;
-    fn do_it<M>(pool: &Arc<SharedPool<M>>) -> impl Future<Item = (), Error = M::Error> + Send
-    where
-        M: ManageConnection,
-    {
-        let new_shared = Arc::downgrade(pool);
-        let (tx, rx) = oneshot::channel();
-        spawn(lazy(move || {
-            match new_shared.upgrade() {
-                None => Either::A(ok(())),
-                Some(shared) => {
-                    Either::B(shared.manager.connect().then(move |result| {
-                        let mut locked = shared.internals.lock().unwrap();
-                        match result {
-                            Ok(conn) => {
-                                let now = Instant::now();
-                                let conn = IdleConn {
-                                    conn: Conn {
-                                        conn: conn,
-                                        birth: now,
-                                    },
-                                    idle_start: now,
-                                };
-                                locked.pending_conns -= 1;
-                                locked.num_conns += 1;
-                                locked.put_idle_conn(conn);
+    open_connection(pool)
+}
+
+// Open a connection into a slot that has already been counted in `pending_conns`.
+fn open_connection<M>(pool: &Arc<SharedPool<M>>) -> impl Future<Item = (), Error = M::Error> + Send
+where
+    M: ManageConnection,
+{
+    let new_shared = Arc::downgrade(pool);
+    let (tx, rx) = oneshot::channel();
+    let span = trace::connect_span(pool);
+    pool.spawn(lazy(move || match new_shared.upgrade() {
+        None => Either::A(ok(())),
+        Some(shared) => Either::B(
+            trace::instrument(
+                span,
+                connect_with_retry(shared.clone(), M::connect_with_info),
+            )
+            .then(move |result| {
+                if result.is_ok() {
+                    shared.statics.observer.on_connect();
+                }
+                let mut locked = shared.lock();
+                match result {
+                    Ok((conn, info)) => {
+                        let now = shared.statics.clock.now();
+                        let conn = IdleConn {
+                            conn: connection_opened(&shared, &mut locked, conn, info),
+                            idle_start: now,
+                            last_active: now,
+                            last_validated: Some(now),
+                        };
+                        // If the pool was shut down while connecting the connection just
+                        // gets dropped.
+                        if !locked.closed {
+                            locked.num_conns += 1;
+                            locked.put_idle_conn(conn);
+                        }
+                        tx.send(Ok(())).map_err(|_| ())
+                    }
+                    Err(err) => {
+                        connection_failed(&shared, &mut locked);
+                        match err {
+                            RunError::User(e) => tx.send(Err(e)).map_err(|_| ()),
+                            // There's no `M::Error` to hand back for a timeout, so report
+                            // it here instead.
+                            e => {
+                                mem::drop(locked);
+                                sink_connect_error(&shared, e);
                                 tx.send(Ok(())).map_err(|_| ())
                             }
-                            Err(err) => {
-                                locked.pending_conns -= 1;
-                                // TODO: retry?
-                                tx.send(Err(err)).map_err(|_| ())
-                            }
                         }
-                    }))
+                    }
                 }
+            }),
+        ),
+    }));
+    // The spawned future only goes away without sending if the executor
+    // dropped it, e.g. while shutting down, and like a pool that has gone away
+    // that's not an error of the manager's.
+    rx.then(|v| v.unwrap_or(Ok(())))
+}
+
+// Record a connection opened into a slot counted in `pending_conns`. The caller
+// adds it to `num_conns` unless the pool has been shut down.
+fn connection_opened<M>(
+    pool: &SharedPool<M>,
+    locked: &mut PoolInternals<M::Connection>,
+    conn: M::Connection,
+    info: ConnectInfo,
+) -> Conn<M::Connection>
+where
+    M: ManageConnection,
+{
+    let conn = Conn {
+        conn,
+        birth: pool.statics.clock.now(),
+        generation: locked.generation,
+        uses: 0,
+        id: locked.next_id,
+        metadata: HashMap::new(),
+        info,
+    };
+    locked.next_id += 1;
+    locked.pending_conns -= 1;
+    locked.stats.connections_created += 1;
+    locked.circuit.record_success();
+    conn
+}
+
+// Record a failure to open a connection into a slot counted in `pending_conns`.
+fn connection_failed<M>(pool: &SharedPool<M>, locked: &mut PoolInternals<M::Connection>)
+where
+    M: ManageConnection,
+{
+    locked.pending_conns -= 1;
+    locked.stats.connection_errors += 1;
+    pool.statics.observer.on_connect_error();
+    let threshold = pool.statics.circuit_breaker.map(|(t, _)| t);
+    locked.circuit.record_failure(threshold);
+}
+
+// A connection being opened, e.g. by `ManageConnection::connect_with_info`.
+type ConnectFuture<M> = Box<
+    dyn Future<
+            Item = (<M as ManageConnection>::Connection, ConnectInfo),
+            Error = <M as ManageConnection>::Error,
+        > + Send,
+>;
+
+// Attempt to open a connection, retrying failed attempts with a jittered
+// exponential backoff if the pool is configured to do so. Errors from all but
+// the last attempt go to the error sink. Attempts that exceed the create timeout
+// fail with `RunError::TimedOut`.
+fn connect_with_retry<M, F>(
+    pool: Arc<SharedPool<M>>,
+    connect: F,
+) -> impl Future<Item = (M::Connection, ConnectInfo), Error = RunError<M::Error>> + Send
+where
+    M: ManageConnection,
+    F: Fn(&M) -> ConnectFuture<M> + Send + 'static,
+{
+    loop_fn(0, move |attempt| {
+        let pool = pool.clone();
+        let connect = connect(&pool.manager);
+        let connect = match pool.statics.create_timeout {
+            Some(timeout) => Either::A(Timeout::new(connect, timeout).map_err(timeout_error)),
+            None => Either::B(connect.map_err(RunError::User)),
+        };
+        connect.then(move |r| match r {
+            Ok(conn) => Either::A(ok(Loop::Break(conn))),
+            Err(e) if attempt >= pool.statics.connection_retries => Either::A(Err(e).into_future()),
+            Err(e) => {
+                sink_connect_error(&pool, e);
+                let delay = pool.statics.retry_delay(attempt);
+                Either::B(
+                    Delay::new(Instant::now() + delay)
+                        .then(move |_| Ok(Loop::Continue(attempt + 1))),
+                )
             }
-        }));
-        rx.then(|v| match v {
-            Ok(o) => o,
-            Err(_) => panic!(),
         })
+    })
+}
+
+// Convert the error from a `Timeout` of a future that fails with a user error.
+// Bound a future by `timeout`, or let it take as long as it takes if there is
+// none.
+fn maybe_timeout<F>(
+    f: F,
+    timeout: Option<Duration>,
+) -> impl Future<Item = F::Item, Error = timeout::Error<F::Error>>
+where
+    F: Future,
+{
+    match timeout {
+        Some(timeout) => Either::A(Timeout::new(f, timeout)),
+        None => Either::B(f.map_err(timeout::Error::inner)),
+    }
+}
+
+fn timeout_error<E>(e: timeout::Error<E>) -> RunError<E> {
+    if e.is_elapsed() || e.is_timer() {
+        RunError::TimedOut
+    } else {
+        RunError::User(e.into_inner().unwrap())
+    }
+}
+
+// Send an error from opening a connection to the error sink.
+fn sink_connect_error<M>(pool: &SharedPool<M>, error: RunError<M::Error>)
+where
+    M: ManageConnection,
+{
+    match error {
+        RunError::User(e) => pool
+            .statics
+            .error_sink
+            .sink_with_context(e, ErrorContext::Connect),
+        _ => pool
+            .statics
+            .error_sink
+            .sink_create_timeout(CreateTimeoutError {
+                timeout: pool.statics.create_timeout.unwrap_or_default(),
+            }),
     }
+}
 
-    do_it(pool)
+// Whether a connection has outlived `max_lifetime`.
+fn is_expired<M>(pool: &SharedPool<M>, conn: &Conn<M::Connection>) -> bool
+where
+    M: ManageConnection,
+{
+    pool.statics
+        .max_lifetime
+        .is_some_and(|lifetime| pool.statics.clock.now() - conn.birth >= lifetime)
 }
 
+// A filter on the connections a checkout will take.
+type Matches<C> = Arc<dyn Fn(&C) -> bool + Send + Sync>;
+
+// Take an idle connection, validating it if necessary. Gives up once `deadline`
+// passes, as if there were no idle connections. Unless `reserved` is set, idle
+// connections needed for unused reserved slots aren't taken. If `matches` is
+// given, the longest idle connection it accepts is taken instead of the one
+// picked by the selection policy.
 fn get_idle_connection<M>(
     inner: Arc<SharedPool<M>>,
+    deadline: Option<Instant>,
+    reserved: bool,
+    matches: Option<Matches<M::Connection>>,
 ) -> impl Future<Item = Conn<M::Connection>, Error = Arc<SharedPool<M>>> + Send
 where
     M: ManageConnection + Send,
     M::Connection: Send,
     M::Error: Send,
 {
-    loop_fn(inner, |inner| {
+    loop_fn(inner, move |inner| {
         let pool = inner.clone();
-        let mut internals = inner.internals.lock().unwrap();
-        if let Some(conn) = internals.conns.pop_front() {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Either::B(Err(pool).into_future());
+        }
+        let mut internals = inner.lock();
+        if !reserved && !internals.can_serve_unreserved(1) {
+            mem::drop(internals);
+            return Either::B(Err(pool).into_future());
+        }
+        let selected = match matches {
+            Some(ref matches) => internals
+                .conns
+                .iter()
+                .position(|conn| matches(&conn.conn.conn)),
+            None => inner.statics.selection_policy.select(&IdleConnections {
+                conns: &internals.conns,
+                now: inner.statics.clock.now(),
+            }),
+        };
+        let conn = selected.and_then(|index| internals.conns.remove(index));
+        if let Some(conn) = conn {
+            if inner.statics.expire_on_check_out && is_expired(&inner, &conn.conn) {
+                let _ = drop_connections(&pool, &mut internals, vec![conn.conn.conn]);
+                mem::drop(internals);
+                return Either::B(Ok(Loop::Continue(pool)).into_future());
+            }
+
             // Spin up a new connection if necessary to retain our minimum idle count
-            if internals.num_conns + internals.pending_conns < pool.statics.max_size {
+            if internals.num_conns + internals.pending_conns < internals.max_size {
                 let f = Pool::replenish_idle_connections_locked(&pool, &mut internals);
-                pool.spawn(pool.sink_error(f));
+                pool.spawn(pool.sink_error(ErrorContext::Connect, f));
             }
 
             // Go ahead and release the lock here.
             mem::drop(internals);
 
-            if pool.statics.test_on_check_out {
-                let birth = conn.conn.birth;
-                Either::A(
-                    pool.manager
-                        .is_valid(conn.conn.conn)
-                        .then(move |r| match r {
-                            Ok(conn) => Ok(Loop::Break(Conn {
-                                conn: conn,
-                                birth: birth,
-                            })),
-                            Err((_, conn)) => {
-                                {
-                                    let mut locked = pool.internals.lock().unwrap();
-                                    drop_connections(&pool, &mut locked, vec![conn]);
-                                }
-                                Ok(Loop::Continue(pool))
-                            }
-                        }),
-                )
+            let fresh = pool.statics.background_validation.is_some_and(|interval| {
+                let now = pool.statics.clock.now();
+                conn.last_validated
+                    .is_some_and(|validated| now - validated < interval)
+            });
+            if pool.statics.test_on_check_out && !fresh {
+                let (conn, meta) = conn.conn.replace(());
+                let validate = pool.manager.is_valid(conn);
+                let validate = match deadline {
+                    Some(deadline) => {
+                        Either::A(Timeout::new_at(validate, deadline).map_err(|e| e.into_inner()))
+                    }
+                    None => Either::B(validate.map_err(Some)),
+                };
+                Either::A(validate.then(move |r| match r {
+                    Ok(conn) => Ok(Loop::Break(meta.replace(conn).1)),
+                    // The connection went with the abandoned validation, so just
+                    // release its slot.
+                    Err(None) => {
+                        {
+                            let mut locked = pool.lock();
+                            locked.num_conns -= 1;
+                            let _ = drop_connections(&pool, &mut locked, vec![]);
+                        }
+                        Ok(Loop::Continue(pool))
+                    }
+                    Err(Some((e, conn))) => {
+                        pool.statics
+                            .error_sink
+                            .sink_with_context(e, ErrorContext::CheckOut);
+                        {
+                            let mut locked = pool.lock();
+                            let _ = drop_connections(&pool, &mut locked, vec![conn]);
+                        }
+                        Ok(Loop::Continue(pool))
+                    }
+                }))
             } else {
                 Either::B(Ok(Loop::Break(conn.conn)).into_future())
             }
@@ -548,96 +2582,966 @@ where
     })
 }
 
-// Drop connections
-// NB: This is called with the pool lock held.
-fn drop_connections<'a, L, M>(
-    pool: &Arc<SharedPool<M>>,
-    mut internals: L,
-    to_drop: Vec<M::Connection>,
-) -> Box<Future<Item = (), Error = M::Error> + Send>
+// Check out a connection, waiting for one to be returned or created if none
+// are idle. `reserved` is set if the checkout uses a reserved slot.
+fn get_connection<M, E>(
+    inner: Arc<SharedPool<M>>,
+    timeout: Option<Duration>,
+    priority: Priority,
+    reserved: bool,
+) -> impl Future<Item = Conn<M::Connection>, Error = RunError<E>> + Send
 where
-    L: BorrowMut<MutexGuard<'a, PoolInternals<M::Connection>>>,
     M: ManageConnection,
+    E: From<M::Error> + Send,
 {
-    let internals = internals.borrow_mut();
+    let pool = inner.clone();
+    let span = trace::checkout_span(&inner);
+    trace::instrument(
+        span,
+        lazy(move || {
+            let requested = Instant::now();
+            let deadline = timeout.map(|timeout| requested + timeout);
+            check_out(inner, deadline, priority, reserved)
+                .and_then(move |conn| customize_connection(pool, conn, requested))
+        }),
+    )
+}
 
-    internals.num_conns -= to_drop.len() as u32;
-    // We might need to spin up more connections to maintain the idle limit, e.g.
-    // if we hit connection lifetime limits
-    let f = if internals.num_conns + internals.pending_conns < pool.statics.max_size {
-        Either::A(Pool::replenish_idle_connections_locked(
-            pool,
-            &mut *internals,
-        ))
-    } else {
-        Either::B(ok(()))
-    };
+// Take an idle connection, or else queue for one until `deadline`, opening a
+// connection for the checkout if the pool has room.
+fn check_out<M, E>(
+    inner: Arc<SharedPool<M>>,
+    deadline: Option<Instant>,
+    priority: Priority,
+    reserved: bool,
+) -> impl Future<Item = Conn<M::Connection>, Error = RunError<E>> + Send
+where
+    M: ManageConnection,
+    E: From<M::Error> + Send,
+{
+    get_idle_connection(inner, deadline, reserved, None).then(move |r| match r {
+        Ok(conn) => {
+            trace::record_path("idle");
+            Either::A(ok(conn))
+        }
+        Err(inner) => {
+            let (tx, rx) = oneshot::channel();
+            let (err_tx, err_rx) = oneshot::channel();
+            let start = Instant::now();
+            let mut timeout = deadline.map(|deadline| deadline.saturating_duration_since(start));
+            if timeout == Some(Duration::from_secs(0)) {
+                inner.statics.observer.on_timeout();
+                return Either::B(Either::B(Err(RunError::TimedOut).into_future()));
+            }
+            {
+                let mut locked = inner.lock();
+                if locked.closed {
+                    return Either::B(Either::B(Err(RunError::PoolClosed).into_future()));
+                }
+                let will_connect = locked.num_conns + locked.pending_conns < locked.max_size;
+                if let Some(max_waiters) = inner.statics.max_waiters {
+                    if locked.waiters.len() as u32 >= max_waiters {
+                        return Either::B(Either::B(Err(RunError::QueueFull).into_future()));
+                    }
+                }
+                if let Some((_, cooldown)) = inner.statics.circuit_breaker {
+                    if locked.circuit.is_open(cooldown, will_connect) {
+                        return Either::B(Either::B(Err(RunError::CircuitOpen).into_future()));
+                    }
+                }
+                // Only a connection being returned can serve this checkout.
+                let must_queue = !will_connect && locked.conns.is_empty();
+                match inner.statics.queue_strategy {
+                    QueueStrategy::Fail if must_queue => {
+                        return Either::B(Either::B(Err(RunError::QueueFull).into_future()));
+                    }
+                    QueueStrategy::WaitBounded(bound) if must_queue => {
+                        timeout = Some(timeout.map_or(bound, |timeout| min(timeout, bound)))
+                    }
+                    _ => {}
+                }
+                if reserved {
+                    locked.waiters.push_reserved(tx);
+                } else {
+                    locked.waiters.push(priority, tx);
+                }
+                // A connection may have been returned since we looked for an idle
+                // one. Hand it to the next waiting checkout rather than letting a
+                // later checkout jump the queue by taking it.
+                match locked.conns.pop_front() {
+                    Some(conn) => {
+                        trace::record_path("queued");
+                        locked.put_idle_conn(conn)
+                    }
+                    None if will_connect => {
+                        trace::record_path("opened");
+                        // The connection is opened for this checkout, so it gets
+                        // the error if opening it fails, unless it has already
+                        // been served or given up.
+                        let f = add_connection(&inner, &mut locked);
+                        let sink = inner.statics.error_sink.boxed_clone();
+                        inner.spawn(f.or_else(move |e| {
+                            if let Err(e) = err_tx.send(e) {
+                                sink.sink_with_context(e, ErrorContext::Connect);
+                            }
+                            Ok(())
+                        }));
+                    }
+                    None => trace::record_path("queued"),
+                }
+            }
 
-    // Maybe unlock. If we're passed a MutexGuard, this will unlock. If we're passed a
-    // &mut MutexGuard it won't.
-    mem::drop(internals);
+            let waiter = Waiter {
+                pool: inner.clone(),
+                rx,
+                err_rx: Some(err_rx),
+                done: false,
+            };
 
-    // And drop the connections
-    // TODO: connection_customizer::on_release! That would require figuring out the
-    // locking situation though
-    Box::new(f)
+            Either::B(Either::A(inner.or_timeout(waiter, timeout).then(
+                move |r| match r {
+                    Ok(Some(Ok(conn))) => {
+                        let mut locked = inner.lock();
+                        let wait = start.elapsed();
+                        locked.stats.record_wait(wait);
+                        #[cfg(feature = "histogram")]
+                        inner.histogram.record(wait);
+                        Ok(conn)
+                    }
+                    // The waiter has closed its receiver, so its sender is
+                    // canceled like that of a dropped waiter.
+                    Ok(Some(Err(e))) => {
+                        inner.lock().waiters.cancel();
+                        Err(RunError::User(e.into()))
+                    }
+                    // Dropping the waiter has already removed our sender from
+                    // the queue.
+                    Ok(None) => {
+                        inner.statics.observer.on_timeout();
+                        Err(RunError::TimedOut)
+                    }
+                    // Waiters are only abandoned when the pool shuts down.
+                    Err(_) => Err(RunError::PoolClosed),
+                },
+            )))
+        }
+    })
 }
 
-fn drop_idle_connections<'a, M>(
-    pool: &Arc<SharedPool<M>>,
-    internals: MutexGuard<'a, PoolInternals<M::Connection>>,
-    to_drop: Vec<IdleConn<M::Connection>>,
-) -> Box<Future<Item = (), Error = M::Error> + Send>
+// Check out a connection that `matches` accepts, e.g. one to a particular
+// backend, opening one with `connect` if none is idle. If the pool is full, the
+// longest idle connection is closed to make room, or if every connection is
+// checked out, the next one returned to the checkout is swapped for a new one.
+fn get_matching_connection<M, F>(
+    inner: Arc<SharedPool<M>>,
+    timeout: Option<Duration>,
+    reserved: bool,
+    matches: Matches<M::Connection>,
+    connect: F,
+) -> impl Future<Item = Conn<M::Connection>, Error = RunError<M::Error>> + Send
 where
     M: ManageConnection,
+    F: Fn(&M) -> ConnectFuture<M> + Send + 'static,
 {
-    let to_drop = to_drop.into_iter().map(|c| c.conn.conn).collect();
-    drop_connections(pool, internals, to_drop)
+    let pool = inner.clone();
+    let span = trace::checkout_span(&inner);
+    trace::instrument(
+        span,
+        lazy(move || {
+            let requested = Instant::now();
+            let deadline = timeout.map(|timeout| requested + timeout);
+            get_idle_connection(inner, deadline, reserved, Some(matches.clone()))
+                .then(move |r| {
+                    let inner = match r {
+                        Ok(conn) => {
+                            trace::record_path("idle");
+                            return Either::A(ok(conn));
+                        }
+                        Err(inner) => inner,
+                    };
+                    let mut locked = inner.lock();
+                    if locked.closed {
+                        return Either::A(err(RunError::PoolClosed));
+                    }
+                    let has_room = locked.num_conns + locked.pending_conns < locked.max_size;
+                    let evict = if has_room {
+                        None
+                    } else {
+                        locked
+                            .conns
+                            .iter()
+                            .position(|conn| !matches(&conn.conn.conn))
+                    };
+                    if (reserved || locked.can_serve_unreserved(1)) && (has_room || evict.is_some())
+                    {
+                        if let Some((_, cooldown)) = inner.statics.circuit_breaker {
+                            if locked.circuit.is_open(cooldown, true) {
+                                return Either::A(err(RunError::CircuitOpen));
+                            }
+                        }
+                        locked.pending_conns += 1;
+                        match evict.and_then(|index| locked.conns.remove(index)) {
+                            Some(conn) => {
+                                let _ = drop_idle_connections(&inner, locked, vec![conn]);
+                            }
+                            None => mem::drop(locked),
+                        }
+                        trace::record_path("opened");
+                        return Either::B(Either::A(open_for_checkout(&inner, connect, deadline)));
+                    }
+                    mem::drop(locked);
+
+                    Either::B(Either::B(
+                        check_out(inner.clone(), deadline, Priority::Normal, reserved).and_then(
+                            move |conn| {
+                                if matches(&conn.conn) {
+                                    return Either::A(ok(conn));
+                                }
+                                {
+                                    let mut locked = inner.lock();
+                                    locked.pending_conns += 1;
+                                    let _ = drop_connections(&inner, locked, vec![conn.conn]);
+                                }
+                                trace::record_path("opened");
+                                Either::B(open_for_checkout(&inner, connect, deadline))
+                            },
+                        ),
+                    ))
+                })
+                .and_then(move |conn| customize_connection(pool, conn, requested))
+        }),
+    )
 }
 
-// Reap connections if necessary.
-// NB: This is called with the pool lock held.
-fn reap_connections<'a, M>(
+// Open a connection with `connect` into a slot counted in `pending_conns`, for
+// a checkout that waits for it until `deadline`. If the checkout stops waiting
+// first, the connection goes to the pool like any other.
+fn open_for_checkout<M, F>(
     pool: &Arc<SharedPool<M>>,
-    mut internals: MutexGuard<'a, PoolInternals<M::Connection>>,
-) -> impl Future<Item = (), Error = M::Error> + Send
+    connect: F,
+    deadline: Option<Instant>,
+) -> impl Future<Item = Conn<M::Connection>, Error = RunError<M::Error>> + Send
 where
     M: ManageConnection,
+    F: Fn(&M) -> ConnectFuture<M> + Send + 'static,
 {
-    let now = Instant::now();
-    let (to_drop, preserve) = internals.conns.drain(..).partition2(|conn| {
-        let mut reap = false;
-        if let Some(timeout) = pool.statics.idle_timeout {
-            reap |= now - conn.idle_start >= timeout;
-        }
-        if let Some(lifetime) = pool.statics.max_lifetime {
-            reap |= now - conn.conn.birth >= lifetime;
+    let (tx, rx) = oneshot::channel();
+    let shared = pool.clone();
+    let connect = connect_with_retry(pool.clone(), connect);
+    pool.spawn(
+        trace::instrument(trace::connect_span(pool), connect).then(move |result| {
+            if result.is_ok() {
+                shared.statics.observer.on_connect();
+            }
+            let mut locked = shared.lock();
+            match result {
+                Ok((conn, info)) => {
+                    let conn = connection_opened(&shared, &mut locked, conn, info);
+                    // If the pool was shut down while connecting the connection just
+                    // gets dropped.
+                    if !locked.closed {
+                        locked.num_conns += 1;
+                        if let Err(Ok(conn)) = tx.send(Ok(conn)) {
+                            let now = shared.statics.clock.now();
+                            locked.put_idle_conn(IdleConn::make_idle(conn, now));
+                        }
+                    }
+                }
+                Err(e) => {
+                    connection_failed(&shared, &mut locked);
+                    if let Err(Err(e)) = tx.send(Err(e)) {
+                        mem::drop(locked);
+                        sink_connect_error(&shared, e);
+                    }
+                }
+            }
+            Ok(())
+        }),
+    );
+
+    let opening = Opening {
+        pool: pool.clone(),
+        rx,
+    };
+    let shared = pool.clone();
+    let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    maybe_timeout(opening, timeout).then(move |r| match r {
+        Ok(conn) => Ok(conn),
+        Err(ref e) if e.is_elapsed() || e.is_timer() => {
+            shared.statics.observer.on_timeout();
+            Err(RunError::TimedOut)
         }
-        reap
-    });
-    internals.conns = preserve;
-    drop_idle_connections(pool, internals, to_drop)
+        Err(e) => Err(e.into_inner().unwrap()),
+    })
 }
 
-fn schedule_one_reaping<M>(
-    pool: &SharedPool<M>,
-    interval: Interval,
-    weak_shared: Weak<SharedPool<M>>,
-) where
+// The outcome `open_for_checkout` hands to its checkout.
+type Opened<M> =
+    Result<Conn<<M as ManageConnection>::Connection>, RunError<<M as ManageConnection>::Error>>;
+
+// Waits for the connection opened by `open_for_checkout`. If it's dropped
+// first, a connection that was handed over in the meantime goes back to the
+// pool rather than leaking its slot.
+struct Opening<M>
+where
     M: ManageConnection,
 {
-    pool.spawn(
-        interval
-            .into_future()
-            .map_err(|_| ())
-            .and_then(move |(_, interval)| match weak_shared.upgrade() {
-                None => Either::A(ok(())),
-                Some(shared) => {
-                    let shared2 = shared.clone();
-                    let locked = shared.internals.lock().unwrap();
-                    Either::B(
+    pool: Arc<SharedPool<M>>,
+    rx: oneshot::Receiver<Opened<M>>,
+}
+
+impl<M> Future for Opening<M>
+where
+    M: ManageConnection,
+{
+    type Item = Conn<M::Connection>;
+    type Error = RunError<M::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.rx.poll() {
+            Ok(Async::Ready(r)) => r.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // The connection was dropped because the pool was shut down.
+            Err(_) => Err(RunError::PoolClosed),
+        }
+    }
+}
+
+impl<M> Drop for Opening<M>
+where
+    M: ManageConnection,
+{
+    fn drop(&mut self) {
+        // With the lock held, the connection is either handed over already or
+        // will find the receiver closed.
+        let mut locked = self.pool.lock();
+        let conn = self.rx.try_recv();
+        self.rx.close();
+        if let Ok(Some(Ok(conn))) = conn {
+            if locked.closed || conn.generation != locked.generation {
+                let _ = drop_connections(&self.pool, locked, vec![conn.conn]);
+            } else {
+                let now = self.pool.statics.clock.now();
+                locked.put_idle_conn(IdleConn::make_idle(conn, now));
+            }
+        }
+    }
+}
+
+// Waits for the connection handed to a queued checkout, or for the error from
+// opening one for it. If it's dropped first, e.g. because the checkout timed
+// out or was canceled, its sender is removed from the queue and a connection
+// that was handed over in the meantime goes back to the pool rather than
+// leaking its slot.
+struct Waiter<M>
+where
+    M: ManageConnection,
+{
+    pool: Arc<SharedPool<M>>,
+    rx: oneshot::Receiver<Conn<M::Connection>>,
+    err_rx: Option<oneshot::Receiver<M::Error>>,
+    done: bool,
+}
+
+impl<M> Future for Waiter<M>
+where
+    M: ManageConnection,
+{
+    type Item = Result<Conn<M::Connection>, M::Error>;
+    type Error = oneshot::Canceled;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.rx.poll() {
+            Ok(Async::Ready(conn)) => {
+                self.done = true;
+                return Ok(Async::Ready(Ok(conn)));
+            }
+            Ok(Async::NotReady) => {}
+            Err(canceled) => {
+                self.done = true;
+                return Err(canceled);
+            }
+        }
+        let e = match self.err_rx.as_mut().map(Future::poll) {
+            Some(Ok(Async::Ready(e))) => e,
+            // No connection was opened for this checkout, or it was opened
+            // successfully.
+            Some(Err(_)) => {
+                self.err_rx = None;
+                return Ok(Async::NotReady);
+            }
+            Some(Ok(Async::NotReady)) | None => return Ok(Async::NotReady),
+        };
+        self.done = true;
+        self.rx.close();
+        match self.rx.try_recv() {
+            // A connection was handed over before we stopped waiting.
+            Ok(Some(conn)) => {
+                self.pool
+                    .statics
+                    .error_sink
+                    .sink_with_context(e, ErrorContext::Connect);
+                Ok(Async::Ready(Ok(conn)))
+            }
+            _ => Ok(Async::Ready(Err(e))),
+        }
+    }
+}
+
+impl<M> Drop for Waiter<M>
+where
+    M: ManageConnection,
+{
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        // With the lock held, a sender that is still alive but hasn't sent
+        // anything is still queued.
+        let mut locked = self.pool.lock();
+        let conn = match self.rx.try_recv() {
+            Ok(Some(conn)) => Some(conn),
+            Ok(None) => {
+                self.rx.close();
+                locked.waiters.cancel();
+                None
+            }
+            Err(_) => None,
+        };
+        if let Some(conn) = conn {
+            let excess = locked.num_conns > locked.max_size;
+            if excess || locked.closed || conn.generation != locked.generation {
+                let _ = drop_connections(&self.pool, locked, vec![conn.conn]);
+            } else {
+                let now = self.pool.statics.clock.now();
+                locked.put_idle_conn(IdleConn::make_idle(conn, now));
+            }
+        }
+    }
+}
+
+// Run the connection customizer on a connection that is about to be handed out.
+fn customize_connection<M, E>(
+    pool: Arc<SharedPool<M>>,
+    mut conn: Conn<M::Connection>,
+    requested: Instant,
+) -> impl Future<Item = Conn<M::Connection>, Error = RunError<E>> + Send
+where
+    M: ManageConnection,
+    E: From<M::Error> + Send,
+{
+    pool.statics
+        .connection_customizer
+        .on_acquire(&mut conn.conn)
+        .then(move |r| match r {
+            Ok(()) => {
+                if pool.statics.leak_detection_threshold.is_some() {
+                    let mut locked = pool.lock();
+                    locked.checkouts.insert(
+                        conn.id,
+                        Checkout {
+                            since: pool.statics.clock.now(),
+                            reported: false,
+                        },
+                    );
+                }
+                pool.statics.observer.on_acquire(requested.elapsed());
+                if !pool.warmed.load(Ordering::Relaxed)
+                    && !pool.warmed.swap(true, Ordering::Relaxed)
+                {
+                    let f = {
+                        let mut locked = pool.lock();
+                        Pool::replenish_idle_connections_locked(&pool, &mut locked)
+                    };
+                    pool.spawn(pool.sink_error(ErrorContext::Connect, f));
+                }
+                Ok(conn)
+            }
+            Err(e) => {
+                let locked = pool.lock();
+                let _ = drop_connections(&pool, locked, vec![conn.conn]);
+                Err(RunError::User(e.into()))
+            }
+        })
+}
+
+// Give a checked out connection back to the pool, dropping it instead if it
+// has broken or the user asked for it to be discarded.
+fn put_back<M>(pool: &Arc<SharedPool<M>>, mut conn: Conn<M::Connection>, discard: bool)
+where
+    M: ManageConnection,
+{
+    pool.statics.observer.on_release();
+    pool.statics
+        .connection_customizer
+        .on_release(&mut conn.conn);
+
+    conn.uses += 1;
+    let worn_out = pool.statics.max_uses.is_some_and(|max| conn.uses >= max);
+    if discard || worn_out {
+        return finish_put_back(pool, conn, true);
+    }
+
+    // Supposed to be fast, but do it before locking anyways. Only wait for the
+    // check in the background if it doesn't complete straight away.
+    match poll_now(pool.manager.poll_broken(&mut conn.conn)) {
+        Ok(broken) => recycle(pool, conn, broken.unwrap_or(true)),
+        Err(f) => {
+            let shared = pool.clone();
+            pool.spawn(f.then(move |broken| {
+                recycle(&shared, conn, broken.unwrap_or(true));
+                Ok(())
+            }));
+        }
+    }
+}
+
+// Reset a returned connection with `ManageConnection::recycle` unless it's
+// broken, then requeue it. As with `poll_broken`, only wait for the manager in
+// the background if it doesn't complete straight away.
+fn recycle<M>(pool: &Arc<SharedPool<M>>, conn: Conn<M::Connection>, broken: bool)
+where
+    M: ManageConnection,
+{
+    if broken {
+        return finish_put_back(pool, conn, true);
+    }
+    let (conn, meta) = conn.replace(());
+    let finish = |pool: &Arc<SharedPool<M>>, r, meta: Conn<()>| match r {
+        Ok(conn) => finish_put_back(pool, meta.replace(conn).1, false),
+        Err((e, conn)) => {
+            pool.statics
+                .error_sink
+                .sink_with_context(e, ErrorContext::Return);
+            finish_put_back(pool, meta.replace(conn).1, true)
+        }
+    };
+    match poll_now(pool.manager.recycle(conn)) {
+        Ok(r) => finish(pool, r, meta),
+        Err(f) => {
+            let shared = pool.clone();
+            pool.spawn(f.then(move |r| {
+                finish(&shared, r, meta);
+                Ok(())
+            }));
+        }
+    }
+}
+
+// Requeue a returned connection, or drop it if it's broken.
+fn finish_put_back<M>(pool: &Arc<SharedPool<M>>, conn: Conn<M::Connection>, broken: bool)
+where
+    M: ManageConnection,
+{
+    let mut locked = pool.lock();
+    locked.checkouts.remove(&conn.id);
+    // If the pool has been shrunk, close connections as they come back until
+    // it's within the new maximum size.
+    let excess = locked.num_conns > locked.max_size;
+    if broken {
+        let _ = drop_broken_connection(pool, locked, conn.conn);
+    } else if excess || locked.closed || conn.generation != locked.generation {
+        let _ = drop_connections(pool, locked, vec![conn.conn]);
+    } else if pool.statics.test_on_return {
+        // Validation is asynchronous, so finish returning the connection in the
+        // background.
+        mem::drop(locked);
+        let shared = pool.clone();
+        let (conn, meta) = conn.replace(());
+        let f = pool.manager.is_valid(conn).then(move |r| {
+            let mut locked = shared.lock();
+            match r {
+                Ok(conn)
+                    if !locked.closed
+                        && meta.generation == locked.generation
+                        && locked.num_conns <= locked.max_size =>
+                {
+                    let mut idle =
+                        IdleConn::make_idle(meta.replace(conn).1, shared.statics.clock.now());
+                    idle.last_validated = Some(idle.idle_start);
+                    locked.put_idle_conn(idle);
+                    Either::A(ok(()))
+                }
+                Ok(conn) => Either::B(drop_connections(&shared, locked, vec![conn])),
+                Err((e, conn)) => {
+                    mem::drop(locked);
+                    shared
+                        .statics
+                        .error_sink
+                        .sink_with_context(e, ErrorContext::Return);
+                    let locked = shared.lock();
+                    Either::B(drop_broken_connection(&shared, locked, conn))
+                }
+            }
+        });
+        pool.spawn(pool.sink_error(ErrorContext::Connect, f));
+    } else {
+        locked.put_idle_conn(IdleConn::make_idle(conn, pool.statics.clock.now()));
+    }
+}
+
+// Close a connection that was returned broken. The pool replenishes towards
+// `min_idle` and `min_connections` as for any closed connection, and with
+// `replace_broken` also opens a replacement if that didn't.
+fn drop_broken_connection<'a, M>(
+    pool: &Arc<SharedPool<M>>,
+    mut locked: InternalsGuard<'a, M::Connection>,
+    conn: M::Connection,
+) -> Box<dyn Future<Item = (), Error = M::Error> + Send>
+where
+    M: ManageConnection,
+{
+    let opened = min(
+        locked.num_conns.saturating_add(locked.pending_conns),
+        locked.max_size,
+    );
+    let f = drop_connections(pool, &mut locked, vec![conn]);
+    if pool.statics.replace_broken
+        && !locked.closed
+        && locked.num_conns.saturating_add(locked.pending_conns) < opened
+    {
+        let replace = add_connection(pool, &mut locked);
+        pool.spawn(pool.sink_error(ErrorContext::Connect, replace));
+    }
+    f
+}
+
+// Drop connections
+// NB: This is called with the pool lock held.
+fn drop_connections<'a, L, M>(
+    pool: &Arc<SharedPool<M>>,
+    mut guard: L,
+    to_drop: Vec<M::Connection>,
+) -> Box<dyn Future<Item = (), Error = M::Error> + Send>
+where
+    L: BorrowMut<InternalsGuard<'a, M::Connection>>,
+    M: ManageConnection,
+{
+    let internals = guard.borrow_mut();
+
+    internals.num_conns -= to_drop.len() as u32;
+    if internals.num_conns == 0 {
+        for waiter in internals.drain_waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+    // We might need to spin up more connections to maintain the idle limit, e.g.
+    // if we hit connection lifetime limits
+    let f = if internals.num_conns + internals.pending_conns < internals.max_size {
+        Either::A(Pool::replenish_idle_connections_locked(
+            pool,
+            &mut *internals,
+        ))
+    } else {
+        Either::B(ok(()))
+    };
+
+    // Maybe unlock. If we're passed an InternalsGuard, this will unlock. If we're passed a
+    // &mut InternalsGuard it won't.
+    mem::drop(guard);
+
+    // And drop the connections, after tearing them down if the customizer
+    // wants to. Teardowns are spawned so they still happen if the returned
+    // future is dropped.
+    let customizer = &pool.statics.connection_customizer;
+    let teardowns = to_drop
+        .into_iter()
+        .filter_map(|conn| customizer.on_close(conn))
+        .map(|f| pool.sink_error(ErrorContext::Close, f).then(|_| Ok(())))
+        .collect::<Vec<_>>();
+    if teardowns.is_empty() {
+        return Box::new(f);
+    }
+    let (tx, rx) = oneshot::channel();
+    pool.spawn(join_all(teardowns).map(move |_| {
+        let _ = tx.send(());
+    }));
+    Box::new(rx.then(move |_| f))
+}
+
+fn drop_idle_connections<'a, M>(
+    pool: &Arc<SharedPool<M>>,
+    internals: InternalsGuard<'a, M::Connection>,
+    to_drop: Vec<IdleConn<M::Connection>>,
+) -> Box<dyn Future<Item = (), Error = M::Error> + Send>
+where
+    M: ManageConnection,
+{
+    let to_drop = to_drop.into_iter().map(|c| c.conn.conn).collect();
+    drop_connections(pool, internals, to_drop)
+}
+
+// The maximum number of idle connections examined per lock acquisition while
+// reaping, so a pass over a large pool doesn't stall checkouts.
+const REAP_BATCH_SIZE: usize = 16;
+
+// Reap connections if necessary.
+// The idle queue is walked in batches of at most `REAP_BATCH_SIZE`, releasing
+// the pool lock in between. Connections shifted past the cursor by concurrent
+// checkouts are picked up on the next pass.
+fn reap_connections<M>(pool: &Arc<SharedPool<M>>) -> impl Future<Item = (), Error = M::Error> + Send
+where
+    M: ManageConnection,
+{
+    let now = pool.statics.clock.now();
+    let should_reap = |conn: &IdleConn<M::Connection>| {
+        let mut reap = false;
+        if let Some(timeout) = pool.statics.idle_timeout {
+            reap |= now - conn.idle_start >= timeout;
+        }
+        if let Some(lifetime) = pool.statics.max_lifetime {
+            reap |= now - conn.conn.birth >= lifetime;
+        }
+        reap
+    };
+
+    let mut fs = Vec::new();
+    let mut reaped = 0;
+    let mut cursor = 0;
+    loop {
+        let mut internals = pool.lock();
+        let end = min(cursor + REAP_BATCH_SIZE, internals.conns.len());
+        let mut reap = (cursor..end)
+            .map(|i| should_reap(&internals.conns[i]))
+            .collect::<Vec<_>>();
+        let mut count = reap.iter().filter(|&&r| r).count() as u32;
+        // Don't reap below the minimum number of connections.
+        let floor = min(
+            max(
+                pool.statics.min_connections.unwrap_or(0),
+                internals.adaptive_target,
+            ),
+            internals.max_size,
+        );
+        for r in reap.iter_mut().rev() {
+            if count == 0 || internals.num_conns.saturating_sub(count) >= floor {
+                break;
+            }
+            if *r {
+                *r = false;
+                count -= 1;
+            }
+        }
+        // If the pool has been shrunk, close idle connections in excess of the new
+        // maximum size too.
+        let mut excess = internals
+            .num_conns
+            .saturating_sub(count)
+            .saturating_sub(internals.max_size);
+        for r in reap.iter_mut() {
+            if excess == 0 {
+                break;
+            }
+            if !*r {
+                *r = true;
+                count += 1;
+                excess -= 1;
+            }
+        }
+        let mut to_drop = Vec::with_capacity(count as usize);
+        for (offset, _) in reap.iter().enumerate().rev().filter(|&(_, &r)| r) {
+            to_drop.push(internals.conns.remove(cursor + offset).unwrap());
+        }
+        let last = end == internals.conns.len() + to_drop.len();
+        if last {
+            if let Some(lifetime) = pool.statics.max_lifetime {
+                let expired = internals
+                    .parked
+                    .iter()
+                    .filter(|&(_, conn)| now - conn.birth >= lifetime)
+                    .map(|(&id, _)| id)
+                    .collect::<Vec<_>>();
+                for id in expired {
+                    let conn = internals.parked.remove(&id).unwrap();
+                    to_drop.push(IdleConn::make_idle(conn, now));
+                }
+            }
+        }
+        cursor = end - count as usize;
+        reaped += to_drop.len() as u32;
+        internals.stats.connections_reaped += to_drop.len() as u64;
+        if !to_drop.is_empty() {
+            fs.push(drop_idle_connections(pool, internals, to_drop));
+        }
+        if last {
+            break;
+        }
+    }
+    if reaped > 0 {
+        pool.statics.observer.on_reap(reaped);
+    }
+    FuturesUnordered::from_iter(fs).fold((), |_, _| Ok(()))
+}
+
+// Record the peak demand since the last reaper run, and retarget the number
+// of connections kept open for `adaptive_idle`.
+fn adapt_idle_target<M>(
+    pool: &Arc<SharedPool<M>>,
+) -> impl Future<Item = (), Error = M::Error> + Send
+where
+    M: ManageConnection,
+{
+    let (window, percentile) = match pool.statics.adaptive_idle {
+        Some(adaptive_idle) => adaptive_idle,
+        None => return Either::A(ok(())),
+    };
+    let now = pool.statics.clock.now();
+    let mut locked = pool.lock();
+    let peak = mem::replace(&mut locked.peak_demand, 0);
+    locked.demand_samples.push_back((now, peak));
+    while locked
+        .demand_samples
+        .front()
+        .is_some_and(|&(sampled, _)| now - sampled > window)
+    {
+        locked.demand_samples.pop_front();
+    }
+    let mut peaks = locked
+        .demand_samples
+        .iter()
+        .map(|&(_, peak)| peak)
+        .collect::<Vec<_>>();
+    peaks.sort_unstable();
+    let rank = (percentile / 100.0 * peaks.len() as f64).ceil() as usize;
+    let target = peaks[rank.saturating_sub(1)];
+    locked.adaptive_target = min(target, locked.max_size);
+    Either::B(Pool::replenish_idle_connections_locked(pool, &mut locked))
+}
+
+// Ping idle connections that haven't been active for the keepalive interval,
+// or validated for the background validation interval, putting them back in
+// the idle queue if they're still valid.
+fn ping_idle_connections<M>(pool: &Arc<SharedPool<M>>) -> impl Future<Item = (), Error = ()> + Send
+where
+    M: ManageConnection,
+{
+    let mut to_ping = Vec::new();
+    let keepalive = pool.statics.keepalive_interval;
+    let validation = pool.statics.background_validation;
+    if keepalive.is_some() || validation.is_some() {
+        let now = pool.statics.clock.now();
+        let mut locked = pool.lock();
+        let (stale, preserve) = locked.conns.drain(..).partition2(|conn| {
+            keepalive.is_some_and(|interval| now - conn.last_active >= interval)
+                || validation.is_some_and(|interval| {
+                    conn.last_validated
+                        .is_none_or(|validated| now - validated >= interval)
+                })
+        });
+        locked.conns = preserve;
+        to_ping = stale;
+    }
+
+    let f = FuturesUnordered::from_iter(to_ping.into_iter().map(|idle| {
+        let pool = pool.clone();
+        let idle_start = idle.idle_start;
+        let (conn, meta) = idle.conn.replace(());
+        pool.manager.is_valid(conn).then(move |r| {
+            let mut locked = pool.lock();
+            match r {
+                Ok(conn) if !locked.closed && meta.generation == locked.generation => {
+                    let now = pool.statics.clock.now();
+                    locked.put_idle_conn(IdleConn {
+                        conn: meta.replace(conn).1,
+                        idle_start,
+                        last_active: now,
+                        last_validated: Some(now),
+                    });
+                    Either::A(ok(()))
+                }
+                Ok(conn) => Either::B(drop_connections(&pool, locked, vec![conn])),
+                Err((e, conn)) => {
+                    pool.statics
+                        .error_sink
+                        .sink_with_context(e, ErrorContext::Keepalive);
+                    Either::B(drop_connections(&pool, locked, vec![conn]))
+                }
+            }
+        })
+    }));
+    pool.sink_error(ErrorContext::Connect, f.fold((), |_, _| Ok(())))
+}
+
+// Report connections that have been checked out for longer than the leak
+// detection threshold to the error sink. Each connection is only reported once.
+fn report_leaks<M>(pool: &SharedPool<M>)
+where
+    M: ManageConnection,
+{
+    let threshold = match pool.statics.leak_detection_threshold {
+        Some(threshold) => threshold,
+        None => return,
+    };
+    let mut leaked = Vec::new();
+    {
+        let now = pool.statics.clock.now();
+        let mut locked = pool.lock();
+        for checkout in locked.checkouts.values_mut() {
+            let checked_out_for = now - checkout.since;
+            if !checkout.reported && checked_out_for >= threshold {
+                checkout.reported = true;
+                leaked.push(LeakError { checked_out_for });
+            }
+        }
+    }
+    for error in leaked {
+        pool.statics.error_sink.sink_leak(error);
+    }
+}
+
+// Report the pool to the observer if it has been failing to maintain
+// `min_idle` for longer than the alert threshold. Each episode is only
+// reported once.
+fn report_min_idle_unmet<M>(pool: &SharedPool<M>)
+where
+    M: ManageConnection,
+{
+    let now = pool.statics.clock.now();
+    let mut locked = pool.lock();
+    let idle = locked.conns.len() as u32;
+    let min_idle = min(locked.min_idle.unwrap_or(0), locked.max_size);
+    let warmed = pool.warmed.load(Ordering::Relaxed);
+    if locked.closed || !warmed || idle >= min_idle || locked.num_conns >= locked.max_size {
+        locked.min_idle_unmet_since = None;
+        locked.min_idle_unmet_reported = false;
+        return;
+    }
+    let since = *locked.min_idle_unmet_since.get_or_insert(now);
+    let unmet_for = now - since;
+    if locked.min_idle_unmet_reported || unmet_for < pool.statics.min_idle_alert_threshold {
+        return;
+    }
+    locked.min_idle_unmet_reported = true;
+    mem::drop(locked);
+    pool.statics
+        .observer
+        .on_min_idle_unmet(idle, min_idle, unmet_for);
+}
+
+fn schedule_one_reaping<M>(
+    pool: &SharedPool<M>,
+    interval: Interval,
+    weak_shared: Weak<SharedPool<M>>,
+) where
+    M: ManageConnection,
+{
+    pool.spawn(
+        interval
+            .into_future()
+            .map_err(|_| ())
+            .and_then(move |(_, interval)| match weak_shared.upgrade() {
+                None => Either::A(ok(())),
+                Some(shared) => {
+                    let shared2 = shared.clone();
+                    shared.spawn(ping_idle_connections(&shared));
+                    shared.spawn(
+                        shared.sink_error(ErrorContext::Connect, adapt_idle_target(&shared)),
+                    );
+                    report_leaks(&shared);
+                    report_min_idle_unmet(&shared);
+                    Either::B(
                         shared
-                            .sink_error(reap_connections(&shared, locked))
+                            .sink_error(ErrorContext::Reap, reap_connections(&shared))
                             .then(move |r| {
                                 schedule_one_reaping(&shared2, interval, weak_shared);
                                 r
@@ -650,30 +3554,81 @@ fn schedule_one_reaping<M>(
 
 impl<M: ManageConnection> Pool<M> {
     fn new_inner(builder: Builder<M>, manager: M) -> Pool<M> {
+        // The pool's events are counted before they reach its observer.
+        #[cfg(feature = "metrics")]
+        let (builder, gauges) = {
+            let mut builder = builder;
+            let observer = mem::replace(&mut builder.observer, Box::new(NopPoolObserver));
+            let name = builder.name.as_deref();
+            let observer = pool_metrics::MetricsObserver::new(name, observer);
+            let gauges = pool_metrics::Gauges::new(name);
+            builder.observer = Box::new(observer);
+            (builder, gauges)
+        };
+
         let internals = PoolInternals {
-            waiters: VecDeque::new(),
+            waiters: Waiters::new(),
             conns: VecDeque::new(),
             num_conns: 0,
             pending_conns: 0,
+            max_size: builder.max_size,
+            min_idle: builder.min_idle,
+            generation: 0,
+            stats: Statistics::default(),
+            circuit: CircuitBreaker::default(),
+            closed: false,
+            next_id: 0,
+            checkouts: HashMap::new(),
+            parked: HashMap::new(),
+            idle_waiters: Vec::new(),
+            drain_waiters: Vec::new(),
+            reservations: 0,
+            reserved: 0,
+            peak_demand: 0,
+            demand_samples: VecDeque::new(),
+            adaptive_target: 0,
+            min_idle_unmet_since: None,
+            min_idle_unmet_reported: false,
         };
 
         let shared = Arc::new(SharedPool {
+            warmed: AtomicBool::new(!builder.lazy_min_idle),
             statics: builder,
-            manager: manager,
+            manager,
             internals: Mutex::new(internals),
+            counters: Counters::default(),
+            #[cfg(feature = "metrics")]
+            gauges,
+            #[cfg(feature = "histogram")]
+            histogram: LatencyHistogram::default(),
         });
 
-        if shared.statics.max_lifetime.is_some() || shared.statics.idle_timeout.is_some() {
+        if shared.statics.reaper
+            && (shared.statics.max_lifetime.is_some()
+                || shared.statics.idle_timeout.is_some()
+                || shared.statics.keepalive_interval.is_some()
+                || shared.statics.background_validation.is_some()
+                || shared.statics.leak_detection_threshold.is_some()
+                || shared.statics.adaptive_idle.is_some()
+                || shared.statics.min_idle.is_some())
+        {
             let s = Arc::downgrade(&shared);
-            spawn(lazy(|| {
+            shared.spawn(lazy(|| {
                 s.upgrade().ok_or(()).map(|shared| {
-                    let interval = Interval::new_interval(shared.statics.reaper_rate);
+                    let rate = shared.statics.reaper_rate;
+                    let start =
+                        Instant::now() + rate + random_duration(shared.statics.reaper_jitter);
+                    let interval = Interval::new(start, rate);
                     schedule_one_reaping(&shared, interval, s);
                 })
             }))
         }
 
-        Pool { inner: shared }
+        Pool {
+            connection_timeout: shared.statics.connection_timeout,
+            inner: shared,
+            reservation: None,
+        }
     }
 
     fn spawn<R>(&self, runnable: R)
@@ -684,30 +3639,82 @@ impl<M: ManageConnection> Pool<M> {
         self.inner.spawn(runnable);
     }
 
-    fn sink_error<'a, E, F>(&self, f: F) -> impl Future<Item = F::Item, Error = ()> + Send + 'a
+    fn sink_error<'a, E, F>(
+        &self,
+        context: ErrorContext,
+        f: F,
+    ) -> impl Future<Item = F::Item, Error = ()> + Send + 'a
     where
         F: Future<Error = E> + Send + 'a,
         E: Into<M::Error> + 'a,
     {
-        self.inner.sink_error(f)
+        self.inner.sink_error(context, f)
     }
 
     fn replenish_idle_connections_locked(
         pool: &Arc<SharedPool<M>>,
         internals: &mut PoolInternals<M::Connection>,
     ) -> impl Future<Item = (), Error = M::Error> + Send {
-        let slots_available = pool.statics.max_size - internals.num_conns - internals.pending_conns;
-        let idle = internals.conns.len() as u32;
-        let desired = pool.statics.min_idle.unwrap_or(0);
-        let f = FuturesUnordered::from_iter(
-            (idle..max(idle, min(desired, idle + slots_available)))
-                .map(|_| add_connection(pool, internals)),
+        // The pool can be over its maximum size after being shrunk, so all of
+        // this is saturating.
+        let opened = internals.num_conns.saturating_add(internals.pending_conns);
+        let slots_available = internals.max_size.saturating_sub(opened);
+        // Connections being opened will be idle too, unless a waiting checkout
+        // takes them.
+        let idle = (internals.conns.len() as u32).saturating_add(
+            internals
+                .pending_conns
+                .saturating_sub(internals.waiters.len() as u32),
+        );
+        let warmed = pool.warmed.load(Ordering::Relaxed);
+        let (desired_idle, desired_conns) = if internals.closed || !warmed {
+            (0, 0)
+        } else {
+            (
+                internals.min_idle.unwrap_or(0),
+                max(
+                    pool.statics.min_connections.unwrap_or(0),
+                    internals.adaptive_target,
+                ),
+            )
+        };
+        let wanted = max(
+            desired_idle.saturating_sub(idle),
+            desired_conns.saturating_sub(opened),
         );
-        f.fold((), |_, _| Ok(()))
+        let count = min(wanted, slots_available);
+        match pool.statics.warmup_concurrency {
+            Some(concurrency) if count as usize > concurrency => {
+                // Reserve every slot up front, but only open `concurrency` connections at
+                // a time. The openings are driven in the background so that they still
+                // happen if the returned future is dropped.
+                internals.pending_conns += count;
+                let weak_shared = Arc::downgrade(pool);
+                let (tx, rx) = oneshot::channel();
+                pool.spawn(
+                    stream::iter_ok(0..count)
+                        .map(move |_| match weak_shared.upgrade() {
+                            Some(shared) => Either::A(open_connection(&shared).then(Ok)),
+                            None => Either::B(ok(Ok(()))),
+                        })
+                        .buffer_unordered(concurrency)
+                        .fold(Ok(()), |result, r| Ok::<_, ()>(result.and(r)))
+                        .and_then(move |result| tx.send(result).map_err(|_| ())),
+                );
+                Either::A(rx.then(|r| r.unwrap_or(Ok(()))))
+            }
+            _ => {
+                // `count` is within the available slots, so the connections are
+                // opened directly rather than through `add_connection`'s checks.
+                internals.pending_conns += count;
+                let f = FuturesUnordered::from_iter((0..count).map(|_| open_connection(pool)));
+                Either::B(f.fold((), |_, _| Ok(())))
+            }
+        }
     }
 
     fn replenish_idle_connections(&self) -> impl Future<Item = (), Error = M::Error> + Send {
-        let mut locked = self.inner.internals.lock().unwrap();
+        let mut locked = self.inner.lock();
         Pool::replenish_idle_connections_locked(&self.inner, &mut locked)
     }
 
@@ -716,28 +3723,349 @@ impl<M: ManageConnection> Pool<M> {
         Builder::new()
     }
 
+    /// Returns the name set with `Builder::name`, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.inner.statics.name.as_deref()
+    }
+
     /// Returns information about the current state of the pool.
+    ///
+    /// This does not take the pool lock, so the fields are read individually
+    /// and may be momentarily inconsistent with each other while connections
+    /// are being checked out and returned.
     pub fn state(&self) -> State {
-        let locked = self.inner.internals.lock().unwrap();
+        let counters = &self.inner.counters;
         State {
-            connections: locked.num_conns,
-            idle_connections: locked.conns.len() as u32,
-            _p: (),
+            connections: counters.connections.load(Ordering::Relaxed),
+            idle_connections: counters.idle_connections.load(Ordering::Relaxed),
+            pending_connections: counters.pending_connections.load(Ordering::Relaxed),
+            waiters: counters.waiters.load(Ordering::Relaxed),
+            wait_count: counters.wait_count.load(Ordering::Relaxed),
+            total_wait: Duration::from_nanos(counters.total_wait_nanos.load(Ordering::Relaxed)),
+            max_wait: Duration::from_nanos(counters.max_wait_nanos.load(Ordering::Relaxed)),
+            connections_created: counters.connections_created.load(Ordering::Relaxed),
+            connection_errors: counters.connection_errors.load(Ordering::Relaxed),
+            connections_reaped: counters.connections_reaped.load(Ordering::Relaxed),
         }
     }
 
-    /// Run a closure with a `Connection`.
-    ///
-    /// The closure will be executed on the tokio event loop provided during
-    /// the construction of this pool, so it must be `Send`. The closure's return
-    /// value is also `Send` so that the Future can be consumed in contexts where
-    /// `Send` is needed.
+    /// Zeroes the cumulative fields of `State`, e.g. to compute rates over
+    /// fixed windows, along with the `latency_histogram` if enabled. The
+    /// fields describing the pool as it is right now are unaffected.
     ///
-    /// # Futures 0.3 + Async/Await
+    /// The counters are all reset at once, so a checkout or connection that
+    /// completes concurrently is counted either entirely before the reset or
+    /// entirely after it.
+    pub fn reset_stats(&self) {
+        let mut locked = self.inner.lock();
+        locked.stats = Statistics::default();
+        #[cfg(feature = "histogram")]
+        self.inner.histogram.reset();
+    }
+
+    /// Returns how many checkouts through this handle could proceed right now
+    /// without waiting for a connection to be returned.
     ///
-    /// In order to use this with Futures 0.3 + async/await syntax, use `.boxed().compat()` on the inner future in order to convert it to a version 0.1 Future.
+    /// This counts the idle connections plus the connections the pool could
+    /// still open without exceeding `max_size`, leaving out the unused slots
+    /// reserved by `with_reservation` handles other than this one. It's a
+    /// snapshot that other checkouts may change straight away, meant for
+    /// sizing e.g. an upstream semaphore to the pool rather than for
+    /// guaranteeing that a checkout won't wait. A closed pool has none.
+    pub fn available_permits(&self) -> u32 {
+        let locked = self.inner.lock();
+        if locked.closed {
+            return 0;
+        }
+        let idle = locked.conns.len() as u32;
+        let opened = locked.num_conns.saturating_add(locked.pending_conns);
+        let available = idle.saturating_add(locked.max_size.saturating_sub(opened));
+        // Checkouts without a reserved slot must leave the unused ones free,
+        // while this handle's own unused slots are open to it.
+        let checked_out = locked.num_conns.saturating_sub(idle);
+        let unreserved = locked
+            .max_size
+            .saturating_sub(checked_out.saturating_add(locked.reserved));
+        let own = self.reservation.as_ref().map_or(0, |reservation| {
+            reservation.slots - reservation.in_use.load(Ordering::Relaxed)
+        });
+        min(available, own.saturating_add(unreserved))
+    }
+
+    /// Changes the maximum number of connections managed by the pool.
     ///
-    /// ```ignore
+    /// Growing the pool immediately opens connections for any checkouts
+    /// waiting on one, and to maintain `min_idle`, which is still capped at
+    /// `max_size`. Shrinking the pool never closes connections that are
+    /// checked out; instead they are closed as they are returned until the
+    /// pool is within the new maximum, and idle connections in excess of it
+    /// are closed at the next reaping.
+    pub fn set_max_size(&self, max_size: u32) {
+        assert!(max_size > 0, "max_size must be greater than zero!");
+        let mut locked = self.inner.lock();
+        locked.max_size = max_size;
+        while locked.waiters.len() as u32 > locked.pending_conns
+            && locked.num_conns + locked.pending_conns < max_size
+        {
+            let f = add_connection(&self.inner, &mut locked);
+            self.spawn(self.sink_error(ErrorContext::Connect, f));
+        }
+        let f = Pool::replenish_idle_connections_locked(&self.inner, &mut locked);
+        self.spawn(self.sink_error(ErrorContext::Connect, f));
+    }
+
+    /// Changes the minimum idle connection count maintained by the pool.
+    ///
+    /// Raising it immediately opens connections to reach the new minimum.
+    /// Lowering it never closes connections itself; instead idle connections
+    /// are closed by the reaper as they exceed `idle_timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min_idle` is larger than the pool's current `max_size`.
+    pub fn set_min_idle(&self, min_idle: Option<u32>) {
+        let mut locked = self.inner.lock();
+        if min_idle.is_some_and(|min_idle| min_idle > locked.max_size) {
+            // Don't poison the pool lock.
+            mem::drop(locked);
+            panic!("min_idle must be no larger than max_size");
+        }
+        locked.min_idle = min_idle;
+        let f = Pool::replenish_idle_connections_locked(&self.inner, &mut locked);
+        self.spawn(self.sink_error(ErrorContext::Connect, f));
+    }
+
+    /// Waits until at least `min` connections are idle.
+    ///
+    /// This opens connections to maintain `min_idle` and `min_connections` if
+    /// any are missing, but doesn't open connections beyond them, so `min`
+    /// should be no larger than those. It's useful to wait for a pool built
+    /// with `build_unchecked` to be ready.
+    ///
+    /// The returned future fails if opening one of those connections fails,
+    /// and resolves if the pool is shut down while waiting.
+    pub fn wait_for_idle(&self, min: u32) -> impl Future<Item = (), Error = M::Error> + Send {
+        let inner = self.inner.clone();
+        lazy(move || {
+            let mut locked = inner.lock();
+            if locked.closed || locked.conns.len() as u32 >= min {
+                return Either::A(ok(()));
+            }
+            let (tx, rx) = oneshot::channel();
+            locked.idle_waiters.push((min, tx));
+            let f = Pool::replenish_idle_connections_locked(&inner, &mut locked);
+            Either::B(f.and_then(|_| rx.then(|_| Ok(()))))
+        })
+    }
+
+    /// Opens connections until the pool reaches `min_idle` idle connections
+    /// and `min_connections` total connections, without exceeding `max_size`.
+    ///
+    /// The pool normally does this itself on checkouts and in the reaper; this
+    /// allows it to be warmed up explicitly, e.g. after connections failed to
+    /// open. Connections that are already being opened count towards the
+    /// minimums, so it is safe to call concurrently with checkouts.
+    ///
+    /// The returned future resolves once the connections have been opened, or
+    /// fails with `RunError::PoolClosed` if the pool has been shut down.
+    pub fn replenish(&self) -> impl Future<Item = (), Error = RunError<M::Error>> + Send {
+        if self.is_closed() {
+            return Either::A(err(RunError::PoolClosed));
+        }
+        Either::B(self.replenish_idle_connections().map_err(RunError::User))
+    }
+
+    /// Returns true if the pool has been shut down with `shutdown` or
+    /// `drain`.
+    ///
+    /// A closed pool no longer hands out connections: checkouts, dedicated
+    /// connections and `replenish` fail with `RunError::PoolClosed`.
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock().closed
+    }
+
+    /// Closes all idle connections, and then replenishes the pool back to
+    /// `min_idle`.
+    ///
+    /// Connections that are currently checked out are closed when they are
+    /// returned to the pool rather than being reused. This is useful to force
+    /// all connections to be reopened, e.g. after a database failover.
+    ///
+    /// The returned future resolves once the replacement connections have
+    /// been opened.
+    pub fn clear(&self) -> impl Future<Item = (), Error = M::Error> + Send {
+        let inner = self.inner.clone();
+        lazy(move || {
+            let mut locked = inner.lock();
+            locked.generation += 1;
+            let to_drop = locked.conns.drain(..).collect();
+            drop_idle_connections(&inner, locked, to_drop)
+        })
+    }
+
+    /// Closes the idle connections for which `f` returns false.
+    ///
+    /// This is a more targeted `clear`, e.g. to close the connections older
+    /// than some age, or those to one backend of a `RoundRobin` manager. `f`
+    /// is called on each idle connection straight away, with the pool lock
+    /// held, so it should be quick. Checked out connections are unaffected.
+    ///
+    /// The returned future resolves with the number of connections closed,
+    /// once they have been replaced as needed to maintain `min_idle` and
+    /// `min_connections`.
+    pub fn retain<F>(&self, mut f: F) -> impl Future<Item = u32, Error = M::Error> + Send
+    where
+        F: FnMut(&IdleConnection<M::Connection>) -> bool,
+    {
+        let now = self.inner.statics.clock.now();
+        let mut locked = self.inner.lock();
+        let (keep, to_drop): (VecDeque<_>, Vec<_>) = locked
+            .conns
+            .drain(..)
+            .partition2(|conn| f(&IdleConnection { conn, now }));
+        locked.conns = keep;
+        let closed = to_drop.len() as u32;
+        drop_idle_connections(&self.inner, locked, to_drop).map(move |_| closed)
+    }
+
+    /// Validates every idle connection with `ManageConnection::is_valid` at
+    /// once, closing the ones that fail.
+    ///
+    /// This is useful when all connections are suspect, e.g. after a database
+    /// failover, to weed out broken ones before they are checked out.
+    /// Connections are out of the idle set while they are being validated.
+    ///
+    /// The returned future resolves with the number of connections that
+    /// failed validation, once they have been replaced as needed to maintain
+    /// `min_idle`. The validation finishes even if the future is dropped.
+    pub fn flush_broken(&self) -> impl Future<Item = u32, Error = M::Error> + Send {
+        let inner = self.inner.clone();
+        lazy(move || {
+            let to_check = inner.lock().conns.drain(..).collect::<Vec<_>>();
+            let checks = FuturesUnordered::from_iter(to_check.into_iter().map(|idle| {
+                let pool = inner.clone();
+                let idle_start = idle.idle_start;
+                let (conn, meta) = idle.conn.replace(());
+                pool.manager.is_valid(conn).then(move |r| {
+                    let mut locked = pool.lock();
+                    let (conn, broken) = match r {
+                        Ok(conn) if !locked.closed && meta.generation == locked.generation => {
+                            let now = pool.statics.clock.now();
+                            locked.put_idle_conn(IdleConn {
+                                conn: meta.replace(conn).1,
+                                idle_start,
+                                last_active: now,
+                                last_validated: Some(now),
+                            });
+                            return Either::A(ok(Ok(0)));
+                        }
+                        Ok(conn) => (conn, 0),
+                        Err((_, conn)) => (conn, 1),
+                    };
+                    Either::B(
+                        drop_connections(&pool, locked, vec![conn])
+                            .then(move |r| Ok(r.map(|_| broken))),
+                    )
+                })
+            }));
+            let (tx, rx) = oneshot::channel();
+            inner.spawn(
+                checks
+                    .fold(Ok(0), |total: Result<u32, M::Error>, r| {
+                        Ok(total.and_then(|total| r.map(|broken| total + broken)))
+                    })
+                    .and_then(move |result| tx.send(result).map_err(|_| ())),
+            );
+            rx.then(|r| r.unwrap_or(Ok(0)))
+        })
+    }
+
+    /// Shuts the pool down.
+    ///
+    /// All idle connections are closed, and checkouts that are waiting for a
+    /// connection resolve with `RunError::PoolClosed`, as will any later
+    /// calls to `get` or `run`. Connections that are currently checked out are
+    /// closed when they are returned to the pool.
+    ///
+    /// The returned future resolves once the idle connections have been closed.
+    pub fn shutdown(&self) -> impl Future<Item = (), Error = M::Error> + Send {
+        let mut locked = self.inner.lock();
+        locked.closed = true;
+        locked.waiters.clear();
+        locked.idle_waiters.clear();
+        let mut to_drop = locked
+            .parked
+            .drain()
+            .map(|(_, conn)| conn.conn)
+            .collect::<Vec<_>>();
+        to_drop.extend(locked.conns.drain(..).map(|conn| conn.conn.conn));
+        drop_connections(&self.inner, locked, to_drop)
+    }
+
+    /// Shuts the pool down gracefully, waiting for connections that are
+    /// checked out to be returned.
+    ///
+    /// This shuts the pool down as `shutdown` does, so checkouts fail with
+    /// `RunError::PoolClosed` and connections are closed rather than reused
+    /// as they are returned. The returned future resolves once every
+    /// connection has been closed, which means it never resolves if a
+    /// checked out connection is never returned.
+    pub fn drain(&self) -> impl Future<Item = (), Error = M::Error> + Send {
+        let (tx, rx) = oneshot::channel();
+        self.inner.lock().drain_waiters.push(tx);
+        self.shutdown().and_then(|_| rx.then(|_| Ok(())))
+    }
+
+    /// Returns a histogram of how long checkouts waited for a connection.
+    /// Like `State::wait_count`, only checkouts that had to wait are counted.
+    ///
+    /// Each entry is the inclusive upper bound of a bucket and the number of
+    /// waits that fell into it, in increasing order of bound. The last bucket
+    /// has a bound of `Duration::MAX`.
+    ///
+    /// Requires the `histogram` feature.
+    #[cfg(feature = "histogram")]
+    pub fn latency_histogram(&self) -> [(Duration, u64); LATENCY_BUCKETS] {
+        let mut histogram = [(Duration::from_secs(0), 0); LATENCY_BUCKETS];
+        for (i, entry) in histogram.iter_mut().enumerate() {
+            *entry = (
+                LATENCY_BOUNDS[i],
+                self.inner.histogram.buckets[i].load(Ordering::Relaxed),
+            );
+        }
+        histogram
+    }
+
+    /// Returns information about each of the pool's idle connections, in the
+    /// order they will be checked out with `ConnectionOrder::Fifo`.
+    pub fn detailed_state(&self) -> Vec<ConnectionInfo> {
+        let now = self.inner.statics.clock.now();
+        let locked = self.inner.lock();
+        locked
+            .conns
+            .iter()
+            .map(|conn| ConnectionInfo {
+                age: now - conn.conn.birth,
+                idle_for: now - conn.idle_start,
+            })
+            .collect()
+    }
+
+    /// Run a closure with a `Connection`.
+    ///
+    /// The closure will be executed on the tokio event loop provided during
+    /// the construction of this pool, so it must be `Send`. The closure's return
+    /// value is also `Send` so that the Future can be consumed in contexts where
+    /// `Send` is needed.
+    ///
+    /// # Futures 0.3 + Async/Await
+    ///
+    /// With the `std-future` feature enabled, `run_async` accepts and returns
+    /// `std::future::Future`s directly. Otherwise, in order to use this with
+    /// Futures 0.3 + async/await syntax, use `.boxed().compat()` on the inner
+    /// future in order to convert it to a version 0.1 Future.
+    ///
+    /// ```ignore
     /// // Note that this version of `futures` is 0.3
     /// use futures::compat::{Future01CompatExt}; // trait provides `.compat()`
     ///
@@ -760,68 +4088,789 @@ impl<M: ManageConnection> Pool<M> {
         E: From<M::Error> + Send + 'a,
         T: Send + 'a,
     {
-        let inner = self.inner.clone();
-        let inner2 = inner.clone();
-        lazy(move || {
-            get_idle_connection(inner).then(move |r| match r {
-                Ok(conn) => Either::A(ok(conn)),
-                Err(inner) => {
-                    let (tx, rx) = oneshot::channel();
-                    {
-                        let mut locked = inner.internals.lock().unwrap();
-                        locked.waiters.push_back(tx);
-                        if locked.num_conns + locked.pending_conns < inner.statics.max_size {
-                            let f = add_connection(&inner, &mut locked);
-                            inner.spawn(inner.sink_error(f));
-                        }
-                    }
+        self.run_inner(self.connection_timeout, Priority::Normal, f)
+    }
 
-                    Either::B(inner.or_timeout(rx).then(move |r| match r {
-                        Ok(Some(conn)) => Ok(conn),
-                        _ => Err(RunError::TimedOut),
-                    }))
-                }
+    /// Run a closure with a `Connection`, jumping ahead of lower priority
+    /// checkouts if it has to wait for one.
+    ///
+    /// This behaves like `run`, which uses `Priority::Normal`. See `Priority`
+    /// for how waiting checkouts are ordered.
+    pub fn run_with_priority<'a, T, E, U, F>(
+        &self,
+        priority: Priority,
+        f: F,
+    ) -> impl Future<Item = T, Error = RunError<E>> + Send + 'a
+    where
+        F: FnOnce(M::Connection) -> U + Send + 'a,
+        U: IntoFuture<Item = (T, M::Connection), Error = (E, M::Connection)> + Send + 'a,
+        U::Future: Send + 'a,
+        E: From<M::Error> + Send + 'a,
+        T: Send + 'a,
+    {
+        self.run_inner(self.connection_timeout, priority, f)
+    }
+
+    /// Run a closure with a `Connection`, waiting at most `timeout` for one to
+    /// become available.
+    ///
+    /// This behaves like `run`, but overrides the pool's `connection_timeout`
+    /// for this call only.
+    pub fn run_timeout<'a, T, E, U, F>(
+        &self,
+        timeout: Duration,
+        f: F,
+    ) -> impl Future<Item = T, Error = RunError<E>> + Send + 'a
+    where
+        F: FnOnce(M::Connection) -> U + Send + 'a,
+        U: IntoFuture<Item = (T, M::Connection), Error = (E, M::Connection)> + Send + 'a,
+        U::Future: Send + 'a,
+        E: From<M::Error> + Send + 'a,
+        T: Send + 'a,
+    {
+        self.run_inner(Some(timeout), Priority::Normal, f)
+    }
+
+    /// Run a closure with a `PooledConnection`.
+    ///
+    /// Unlike `run`, the closure doesn't have to hand the connection back
+    /// when its future resolves. Instead it can give the connection back to
+    /// the pool as soon as it's done with it by calling
+    /// `PooledConnection::release`, or by dropping it, and then go on with
+    /// other work without holding the connection.
+    pub fn run_pooled<'a, T, E, U, F>(
+        &self,
+        f: F,
+    ) -> impl Future<Item = T, Error = RunError<E>> + Send + 'a
+    where
+        F: FnOnce(PooledConnection<M>) -> U + Send + 'a,
+        U: IntoFuture<Item = T, Error = E> + Send + 'a,
+        U::Future: Send + 'a,
+        E: From<M::Error> + Send + 'a,
+        T: Send + 'a,
+    {
+        self.get()
+            .map_err(RunError::err_into)
+            .and_then(move |conn| f(conn).into_future().map_err(RunError::User))
+    }
+
+    /// Run a closure with a reference to a `Connection`.
+    ///
+    /// This suits connections whose operations only need `&self`, e.g.
+    /// because they use interior mutability. Since the closure only borrows the
+    /// connection, it doesn't have to hand it back like with `run`: the
+    /// connection is returned to the pool once the closure's future resolves.
+    pub fn run_ref<'a, T, E, U, F>(
+        &self,
+        f: F,
+    ) -> impl Future<Item = T, Error = RunError<E>> + Send + 'a
+    where
+        F: FnOnce(&M::Connection) -> U + Send + 'a,
+        U: IntoFuture<Item = T, Error = E> + Send + 'a,
+        U::Future: Send + 'a,
+        E: From<M::Error> + Send + 'a,
+        T: Send + 'a,
+    {
+        self.get()
+            .map_err(RunError::err_into)
+            .and_then(move |conn| {
+                f(&conn).into_future().then(move |r| {
+                    mem::drop(conn);
+                    r.map_err(RunError::User)
+                })
+            })
+    }
+
+    /// Run a closure with a `Connection`, letting it decide whether the
+    /// connection goes back to the pool.
+    ///
+    /// This behaves like `run`, but the closure also resolves with a
+    /// `Disposition`, whether it succeeds or fails. With `Disposition::Discard`
+    /// the connection is closed rather than returned, e.g. because the closure
+    /// left its session in a state that later users shouldn't inherit, such as
+    /// an aborted transaction.
+    pub fn run_with_disposition<'a, T, E, U, F>(
+        &self,
+        f: F,
+    ) -> impl Future<Item = T, Error = RunError<E>> + Send + 'a
+    where
+        F: FnOnce(M::Connection) -> U + Send + 'a,
+        U: IntoFuture<
+                Item = (T, M::Connection, Disposition),
+                Error = (E, M::Connection, Disposition),
+            > + Send
+            + 'a,
+        U::Future: Send + 'a,
+        E: From<M::Error> + Send + 'a,
+        T: Send + 'a,
+    {
+        self.run_disposition_inner(self.connection_timeout, Priority::Normal, f)
+    }
+
+    fn run_inner<'a, T, E, U, F>(
+        &self,
+        timeout: Option<Duration>,
+        priority: Priority,
+        f: F,
+    ) -> impl Future<Item = T, Error = RunError<E>> + Send + 'a
+    where
+        F: FnOnce(M::Connection) -> U + Send + 'a,
+        U: IntoFuture<Item = (T, M::Connection), Error = (E, M::Connection)> + Send + 'a,
+        U::Future: Send + 'a,
+        E: From<M::Error> + Send + 'a,
+        T: Send + 'a,
+    {
+        self.run_disposition_inner(timeout, priority, move |conn| {
+            f(conn).into_future().then(|r| match r {
+                Ok((t, conn)) => Ok((t, conn, Disposition::Keep)),
+                Err((e, conn)) => Err((e, conn, Disposition::Keep)),
             })
         })
-        .and_then(|conn| {
-            let inner = inner2;
-            let birth = conn.birth;
-            f(conn.conn)
+    }
+
+    fn run_disposition_inner<'a, T, E, U, F>(
+        &self,
+        timeout: Option<Duration>,
+        priority: Priority,
+        f: F,
+    ) -> impl Future<Item = T, Error = RunError<E>> + Send + 'a
+    where
+        F: FnOnce(M::Connection) -> U + Send + 'a,
+        U: IntoFuture<
+                Item = (T, M::Connection, Disposition),
+                Error = (E, M::Connection, Disposition),
+            > + Send
+            + 'a,
+        U::Future: Send + 'a,
+        E: From<M::Error> + Send + 'a,
+        T: Send + 'a,
+    {
+        let inner = self.inner.clone();
+        let permit = self.reserve_slot();
+        let reserved = permit.is_some();
+        get_connection(self.inner.clone(), timeout, priority, reserved).and_then(move |conn| {
+            let (conn, meta) = conn.replace(());
+            let lent = LentConnection {
+                pool: inner,
+                meta: Some(meta),
+                permit,
+            };
+            f(conn)
                 .into_future()
                 .then(move |r| {
-                    let (r, mut conn): (Result<_, E>, _) = match r {
-                        Ok((t, conn)) => (Ok(t), conn),
-                        Err((e, conn)) => (Err(e.into()), conn),
+                    let (r, conn, disposition): (Result<_, E>, _, _) = match r {
+                        Ok((t, conn, disposition)) => (Ok(t), conn, disposition),
+                        Err((e, conn, disposition)) => (Err(e), conn, disposition),
                     };
-                    // Supposed to be fast, but do it before locking anyways.
-                    let broken = inner.manager.has_broken(&mut conn);
-
-                    let mut locked = inner.internals.lock().unwrap();
-                    if broken {
-                        drop_connections(&inner, locked, vec![conn]);
-                    } else {
-                        let conn = IdleConn::make_idle(Conn {
-                            conn: conn,
-                            birth: birth,
-                        });
-                        locked.put_idle_conn(conn);
-                    }
+                    lent.give_back(conn, disposition == Disposition::Discard);
                     r
                 })
-                .map_err(|e| RunError::User(e))
+                .map_err(RunError::User)
+        })
+    }
+
+    /// Retrieves a connection from the pool.
+    ///
+    /// If no connection is idle, the checkout waits for one to be returned or
+    /// opened. Waiting checkouts of the same `Priority` are served in the order
+    /// they started waiting, whether the connection they receive was returned by another
+    /// user or newly opened.
+    ///
+    /// If a connection is opened for the checkout and opening it fails, the
+    /// checkout resolves with `RunError::User` holding the connection error,
+    /// rather than waiting out its timeout.
+    ///
+    /// Unlike `run`, the connection is not handed to a closure. Instead the
+    /// returned `PooledConnection` dereferences to the connection, and gives it
+    /// back to the pool when it is dropped.
+    pub fn get(
+        &self,
+    ) -> impl Future<Item = PooledConnection<M>, Error = RunError<M::Error>> + Send {
+        self.get_inner(self.connection_timeout)
+    }
+
+    /// Retrieves a connection from the pool, waiting at most `timeout` for one
+    /// to become available.
+    ///
+    /// This behaves like `get`, but overrides the pool's `connection_timeout`
+    /// for this call only.
+    pub fn get_timeout(
+        &self,
+        timeout: Duration,
+    ) -> impl Future<Item = PooledConnection<M>, Error = RunError<M::Error>> + Send {
+        self.get_inner(Some(timeout))
+    }
+
+    fn get_inner(
+        &self,
+        timeout: Option<Duration>,
+    ) -> impl Future<Item = PooledConnection<M>, Error = RunError<M::Error>> + Send {
+        let inner = self.inner.clone();
+        let permit = self.reserve_slot();
+        let reserved = permit.is_some();
+        get_connection(self.inner.clone(), timeout, Priority::Normal, reserved).map(move |conn| {
+            PooledConnection {
+                pool: inner,
+                conn: Some(conn),
+                permit,
+            }
+        })
+    }
+
+    /// Returns a stream that checks out a connection from the pool each time
+    /// it is polled.
+    ///
+    /// Each connection is retrieved as with `get`, so once `max_size`
+    /// connections are checked out the stream waits for one to be returned.
+    /// This makes it a natural source of backpressure for e.g.
+    /// `buffer_unordered`. The stream never ends, and a checkout that fails
+    /// is yielded as an error without ending it.
+    pub fn connections(
+        &self,
+    ) -> impl Stream<Item = PooledConnection<M>, Error = RunError<M::Error>> + Send {
+        let pool = self.clone();
+        stream::repeat(()).and_then(move |_| pool.get())
+    }
+
+    /// Retrieves an idle connection from the pool, without waiting.
+    ///
+    /// Resolves with `None` rather than waiting on a connection to be returned
+    /// or opened if no idle connection is available. Idle connections are
+    /// still validated if `test_on_check_out` is set.
+    pub fn try_get(
+        &self,
+    ) -> impl Future<Item = Option<PooledConnection<M>>, Error = RunError<M::Error>> + Send {
+        let inner = self.inner.clone();
+        let requested = Instant::now();
+        let permit = self.reserve_slot();
+        let reserved = permit.is_some();
+        get_idle_connection(self.inner.clone(), None, reserved, None).then(move |r| match r {
+            Ok(conn) => Either::A(customize_connection(inner.clone(), conn, requested).map(
+                move |conn| {
+                    Some(PooledConnection {
+                        pool: inner,
+                        conn: Some(conn),
+                        permit,
+                    })
+                },
+            )),
+            Err(inner) => {
+                if inner.lock().closed {
+                    Either::B(err(RunError::PoolClosed))
+                } else {
+                    Either::B(ok(None))
+                }
+            }
         })
     }
 
+    /// Reclaims a connection parked with `PooledConnection::park`.
+    ///
+    /// Returns `None` if the connection has been closed since it was parked,
+    /// e.g. because it exceeded `max_lifetime`, or because the pool was shut
+    /// down or cleared, or if the token comes from another pool.
+    pub fn unpark(&self, mut token: ParkToken) -> Option<PooledConnection<M>> {
+        if !token.is_from(&self.inner) {
+            return None;
+        }
+        token.pool = None;
+        let mut locked = self.inner.lock();
+        let conn = locked.parked.remove(&token.id)?;
+        if conn.generation != locked.generation {
+            let _ = drop_connections(&self.inner, locked, vec![conn.conn]);
+            return None;
+        }
+        if self.inner.statics.leak_detection_threshold.is_some() {
+            locked.checkouts.insert(
+                conn.id,
+                Checkout {
+                    since: self.inner.statics.clock.now(),
+                    reported: false,
+                },
+            );
+        }
+        Some(PooledConnection {
+            pool: self.inner.clone(),
+            conn: Some(conn),
+            permit: None,
+        })
+    }
+
+    /// Returns a handle to the pool with `slots` of its capacity reserved.
+    ///
+    /// Up to `slots` concurrent checkouts through the returned handle and its
+    /// clones are guaranteed a connection, however contended the pool is:
+    /// other checkouts leave enough capacity for the unused reserved slots,
+    /// and waiting checkouts that use a reserved slot are served before any
+    /// others. Checkouts beyond `slots` share the rest of the pool like any
+    /// other. A checkout uses a reserved slot from when it is started until
+    /// the connection is returned. The reservation is released once the
+    /// handle and all of its clones are dropped.
+    ///
+    /// This allows e.g. services that share a pool between tenants to isolate
+    /// them from each other.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slots` is zero, or if it would make the pool's reservations
+    /// larger than its current `max_size`.
+    pub fn with_reservation(&self, slots: u32) -> Pool<M> {
+        assert!(slots > 0, "slots must be greater than zero!");
+        let mut locked = self.inner.lock();
+        if locked.reservations + slots > locked.max_size {
+            mem::drop(locked);
+            panic!("reservations must be no larger than max_size");
+        }
+        locked.reservations += slots;
+        locked.reserved += slots;
+        mem::drop(locked);
+        Pool {
+            inner: self.inner.clone(),
+            reservation: Some(Arc::new(Reservation {
+                pool: self.inner.clone(),
+                slots,
+                in_use: AtomicU32::new(0),
+            })),
+            connection_timeout: self.connection_timeout,
+        }
+    }
+
+    /// Returns a handle to the pool whose checkouts use a different
+    /// connection timeout.
+    ///
+    /// The handle shares the pool's connections, but checkouts through it and
+    /// its clones wait for `connection_timeout` rather than the one the pool
+    /// was built with, as if it was passed to `get_timeout` or `run_timeout`.
+    /// Like `Builder::connection_timeout`, this takes either a `Duration` or
+    /// an `Option<Duration>`, with `None` waiting indefinitely. It also bounds
+    /// opening dedicated connections through the handle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `connection_timeout` is zero.
+    pub fn with_connection_timeout<T>(&self, connection_timeout: T) -> Pool<M>
+    where
+        T: Into<Option<Duration>>,
+    {
+        let connection_timeout = connection_timeout.into();
+        assert!(
+            connection_timeout != Some(Duration::from_secs(0)),
+            "connection_timeout must be non-zero"
+        );
+        Pool {
+            connection_timeout,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a handle to the pool that doesn't keep it alive, e.g. to store
+    /// in a value that tasks spawned by the pool's users also refer to,
+    /// without creating a reference cycle.
+    ///
+    /// The pool is dropped, closing its idle connections, once the last `Pool`
+    /// handle to it is dropped. Its background tasks such as the reaper only
+    /// hold weak references too, so they stop then.
+    pub fn downgrade(&self) -> WeakPool<M> {
+        WeakPool {
+            inner: Arc::downgrade(&self.inner),
+            reservation: self.reservation.as_ref().map(Arc::downgrade),
+            connection_timeout: self.connection_timeout,
+        }
+    }
+
+    // Use one of this handle's reserved slots for a checkout, if it has one free.
+    fn reserve_slot(&self) -> Option<ReservationPermit<M>> {
+        let reservation = self.reservation.as_ref()?;
+        let mut locked = self.inner.lock();
+        if reservation.in_use.load(Ordering::Relaxed) == reservation.slots {
+            return None;
+        }
+        reservation.in_use.fetch_add(1, Ordering::Relaxed);
+        locked.reserved -= 1;
+        Some(ReservationPermit {
+            reservation: reservation.clone(),
+        })
+    }
+
+    /// Checks whether the pool can currently serve a checkout, e.g. for a
+    /// readiness probe.
+    ///
+    /// An idle connection is checked out as with `try_get`, or if there is
+    /// none, a dedicated connection is opened and validated with
+    /// `ManageConnection::is_valid`. Either connection is then released again.
+    /// Resolves with false if this fails or takes longer than the pool's
+    /// `healthcheck_timeout`. It never fails.
+    pub fn healthcheck(&self) -> impl Future<Item = bool, Error = Infallible> + Send {
+        let timeout = self.inner.statics.healthcheck_timeout;
+        let pool = self.clone();
+        let f = self.try_get().then(move |r| match r {
+            Ok(Some(_)) => Either::A(ok(true)),
+            Ok(None) => Either::B(
+                pool.get_dedicated_with_timeout(timeout, true)
+                    .then(|r| Ok(r.is_ok())),
+            ),
+            Err(_) => Either::A(ok(false)),
+        });
+        Timeout::new(f, timeout).then(|r: Result<bool, timeout::Error<()>>| Ok(r.unwrap_or(false)))
+    }
+
+    /// Returns the pool's connection manager.
+    pub fn manager(&self) -> &M {
+        &self.inner.manager
+    }
+
     /// Get a new dedicated connection that will not be managed by the pool.
     /// An application may want a persistent connection (e.g. to do a
     /// postgres LISTEN) that will not be closed or repurposed by the pool.
     ///
     /// This method allows reusing the manager's configuration but otherwise
-    /// bypassing the pool
+    /// bypassing the pool. The connection customizer's `on_acquire` is run on
+    /// it, so it's set up like the pool's connections. Opening the connection
+    /// is bounded by the pool's `connection_timeout`.
     pub fn dedicated_connection(
         &self,
-    ) -> impl Future<Item = M::Connection, Error = M::Error> + Send {
+    ) -> impl Future<Item = M::Connection, Error = RunError<M::Error>> + Send {
+        self.get_dedicated_inner(self.connection_timeout, false)
+    }
+
+    /// Get a new dedicated connection that will not be managed by the pool,
+    /// giving up with `RunError::TimedOut` if it can't be opened within
+    /// `timeout`.
+    ///
+    /// If `validate` is true, the connection is checked with
+    /// `ManageConnection::is_valid` before it is returned, within the same
+    /// timeout. As with `dedicated_connection`, the connection customizer's
+    /// `on_acquire` is run on it.
+    pub fn get_dedicated_with_timeout(
+        &self,
+        timeout: Duration,
+        validate: bool,
+    ) -> impl Future<Item = M::Connection, Error = RunError<M::Error>> + Send {
+        self.get_dedicated_inner(Some(timeout), validate)
+    }
+
+    fn get_dedicated_inner(
+        &self,
+        timeout: Option<Duration>,
+        validate: bool,
+    ) -> impl Future<Item = M::Connection, Error = RunError<M::Error>> + Send {
+        if self.is_closed() {
+            return Either::A(err(RunError::PoolClosed));
+        }
         let inner = self.inner.clone();
-        inner.manager.connect()
+        let f = self.inner.manager.connect().and_then(move |conn| {
+            let validated = if validate {
+                Either::A(inner.manager.is_valid(conn).map_err(|(e, _)| e))
+            } else {
+                Either::B(ok(conn))
+            };
+            validated.and_then(move |mut conn| {
+                inner
+                    .statics
+                    .connection_customizer
+                    .on_acquire(&mut conn)
+                    .map(|_| conn)
+            })
+        });
+        Either::B(maybe_timeout(f, timeout).map_err(timeout_error))
+    }
+
+    /// Get a new dedicated connection that will not be managed by the pool,
+    /// exactly as the manager opens it.
+    ///
+    /// Unlike `dedicated_connection`, the connection customizer isn't run on
+    /// the connection. Opening it is still bounded by the pool's
+    /// `connection_timeout`.
+    pub fn dedicated_connection_raw(
+        &self,
+    ) -> impl Future<Item = M::Connection, Error = RunError<M::Error>> + Send {
+        if self.is_closed() {
+            return Either::A(err(RunError::PoolClosed));
+        }
+        let f = self.inner.manager.connect();
+        Either::B(maybe_timeout(f, self.connection_timeout).map_err(timeout_error))
+    }
+}
+
+// Slots of a pool's capacity reserved for the checkouts of a handle returned
+// by `Pool::with_reservation` and its clones.
+struct Reservation<M>
+where
+    M: ManageConnection,
+{
+    pool: Arc<SharedPool<M>>,
+    slots: u32,
+    // Only changed with the pool lock held.
+    in_use: AtomicU32,
+}
+
+impl<M> Drop for Reservation<M>
+where
+    M: ManageConnection,
+{
+    fn drop(&mut self) {
+        let mut locked = self.pool.lock();
+        locked.reservations -= self.slots;
+        locked.reserved -= self.slots;
+        // Connections that were kept idle for the reservation may now serve
+        // other waiting checkouts.
+        for conn in mem::take(&mut locked.conns) {
+            locked.put_idle_conn(conn);
+        }
+    }
+}
+
+// A reserved slot used by a checkout, which is freed when it is dropped.
+struct ReservationPermit<M>
+where
+    M: ManageConnection,
+{
+    reservation: Arc<Reservation<M>>,
+}
+
+impl<M> Drop for ReservationPermit<M>
+where
+    M: ManageConnection,
+{
+    fn drop(&mut self) {
+        let mut locked = self.reservation.pool.lock();
+        locked.reserved += 1;
+        self.reservation.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// The bookkeeping for a connection that has been handed to a `run` closure. If
+// the connection never comes back, e.g. because the closure panicked or its
+// future was dropped, this releases its slot in the pool when it is dropped.
+struct LentConnection<M>
+where
+    M: ManageConnection,
+{
+    pool: Arc<SharedPool<M>>,
+    meta: Option<Conn<()>>,
+    permit: Option<ReservationPermit<M>>,
+}
+
+impl<M> LentConnection<M>
+where
+    M: ManageConnection,
+{
+    fn give_back(mut self, conn: M::Connection, discard: bool) {
+        let meta = self.meta.take().unwrap();
+        mem::drop(self.permit.take());
+        put_back(&self.pool, meta.replace(conn).1, discard);
+    }
+}
+
+impl<M> Drop for LentConnection<M>
+where
+    M: ManageConnection,
+{
+    fn drop(&mut self) {
+        mem::drop(self.permit.take());
+        if let Some(meta) = self.meta.take() {
+            let mut locked = self.pool.lock();
+            locked.checkouts.remove(&meta.id);
+            locked.num_conns -= 1;
+            let _ = drop_connections(&self.pool, locked, vec![]);
+        }
+    }
+}
+
+/// A token for a connection parked with `PooledConnection::park`.
+///
+/// If the token is dropped rather than passed to `Pool::unpark`, the parked
+/// connection is closed.
+pub struct ParkToken {
+    id: u64,
+    // The pool the connection is parked in, taken once it's unparked.
+    pool: Option<Weak<dyn Parking>>,
+}
+
+impl ParkToken {
+    // Whether the token's connection is parked in `pool`.
+    fn is_from<M>(&self, pool: &Arc<SharedPool<M>>) -> bool
+    where
+        M: ManageConnection,
+    {
+        self.pool
+            .as_ref()
+            .is_some_and(|parked| parked.as_ptr() as *const () == Arc::as_ptr(pool) as *const ())
+    }
+}
+
+impl fmt::Debug for ParkToken {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ParkToken").field("id", &self.id).finish()
+    }
+}
+
+impl Drop for ParkToken {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take().and_then(|pool| pool.upgrade()) {
+            pool.close_parked(self.id);
+        }
+    }
+}
+
+// Lets a `ParkToken` close its connection without knowing the pool's type.
+trait Parking: Send + Sync {
+    fn close_parked(self: Arc<Self>, id: u64);
+}
+
+impl<M> Parking for SharedPool<M>
+where
+    M: ManageConnection,
+{
+    fn close_parked(self: Arc<Self>, id: u64) {
+        let mut locked = self.lock();
+        if let Some(conn) = locked.parked.remove(&id) {
+            // The session may have been left in any state, so it isn't reused.
+            let _ = drop_connections(&self, locked, vec![conn.conn]);
+        }
+    }
+}
+
+/// A smart pointer wrapping a connection checked out from a `Pool`.
+///
+/// The connection is returned to the pool when this is dropped.
+pub struct PooledConnection<M>
+where
+    M: ManageConnection,
+{
+    pool: Arc<SharedPool<M>>,
+    conn: Option<Conn<M::Connection>>,
+    permit: Option<ReservationPermit<M>>,
+}
+
+impl<M> PooledConnection<M>
+where
+    M: ManageConnection,
+{
+    /// Closes the connection instead of returning it to the pool.
+    ///
+    /// This is useful when the connection is known to be unusable, e.g. after
+    /// a fatal protocol error that `ManageConnection::has_broken` doesn't
+    /// detect. The pool will open a replacement if needed to maintain
+    /// `min_idle`.
+    pub fn invalidate(mut self) {
+        mem::drop(self.permit.take());
+        if let Some(conn) = self.conn.take() {
+            put_back(&self.pool, conn, true);
+        }
+    }
+
+    /// Returns the connection to the pool.
+    ///
+    /// This is the same as dropping it, but makes giving the connection back
+    /// early, e.g. in `Pool::run_pooled`, explicit.
+    pub fn release(self) {
+        mem::drop(self);
+    }
+
+    /// Parks the connection in the pool, so that it can be reclaimed later
+    /// with `Pool::unpark`.
+    ///
+    /// A parked connection isn't handed to any other checkout, so session
+    /// state, e.g. an open transaction, is preserved while the caller does
+    /// unrelated work. It's still closed by the reaper once it exceeds
+    /// `max_lifetime`, and when the pool is shut down.
+    pub fn park(mut self) -> ParkToken {
+        mem::drop(self.permit.take());
+        let conn = self.conn.take().unwrap();
+        let id = conn.id;
+        let mut locked = self.pool.lock();
+        locked.checkouts.remove(&id);
+        if locked.closed || conn.generation != locked.generation {
+            let _ = drop_connections(&self.pool, locked, vec![conn.conn]);
+        } else {
+            locked.parked.insert(id, conn);
+        }
+        let pool: Arc<dyn Parking> = self.pool.clone();
+        ParkToken {
+            id,
+            pool: Some(Arc::downgrade(&pool)),
+        }
+    }
+
+    /// Returns how long ago the connection was opened.
+    pub fn age(&self) -> Duration {
+        self.pool.statics.clock.now() - self.conn.as_ref().unwrap().birth
+    }
+
+    /// Returns the number of times the connection was checked out before
+    /// this checkout.
+    pub fn uses(&self) -> u64 {
+        self.conn.as_ref().unwrap().uses
+    }
+
+    /// Returns the information the manager recorded when it opened the
+    /// connection, see `ManageConnection::connect_with_info`.
+    pub fn connect_info(&self) -> &ConnectInfo {
+        &self.conn.as_ref().unwrap().info
+    }
+
+    /// Returns this connection's metadata of type `T`, creating it with
+    /// `Default` if it has none yet.
+    ///
+    /// Metadata stays with the connection across checkouts until it is
+    /// closed, so it can be used to cache state tied to the connection, e.g.
+    /// which statements have already been prepared on it.
+    pub fn metadata<T>(&mut self) -> &mut T
+    where
+        T: Default + Send + Sync + 'static,
+    {
+        self.conn
+            .as_mut()
+            .unwrap()
+            .metadata
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()))
+            .downcast_mut()
+            .unwrap()
+    }
+}
+
+impl<M> fmt::Debug for PooledConnection<M>
+where
+    M: ManageConnection,
+    M::Connection: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.conn.as_ref().unwrap().conn, fmt)
+    }
+}
+
+impl<M> Deref for PooledConnection<M>
+where
+    M: ManageConnection,
+{
+    type Target = M::Connection;
+
+    fn deref(&self) -> &M::Connection {
+        &self.conn.as_ref().unwrap().conn
+    }
+}
+
+impl<M> DerefMut for PooledConnection<M>
+where
+    M: ManageConnection,
+{
+    fn deref_mut(&mut self) -> &mut M::Connection {
+        &mut self.conn.as_mut().unwrap().conn
+    }
+}
+
+impl<M> Drop for PooledConnection<M>
+where
+    M: ManageConnection,
+{
+    fn drop(&mut self) {
+        // Free the reserved slot first, so that the connection is kept for it
+        // rather than handed to a checkout without a reservation.
+        mem::drop(self.permit.take());
+        if let Some(conn) = self.conn.take() {
+            put_back(&self.pool, conn, false);
+        }
     }
 }
@@ -0,0 +1,105 @@
+//! Pool metrics reported through the `metrics` crate facade, with the
+//! `metrics` feature.
+
+use std::fmt;
+use std::time::Duration;
+
+use metrics::{Counter, Gauge, Histogram, Label};
+
+use {PoolInternals, PoolObserver};
+
+// Labels a pool's metrics with its name, if it has one.
+fn labels(name: Option<&str>) -> Vec<Label> {
+    name.map(|name| Label::new("pool", name.to_owned()))
+        .into_iter()
+        .collect()
+}
+
+// The gauges of a pool, set whenever the pool lock is released.
+pub(crate) struct Gauges {
+    connections: Gauge,
+    idle: Gauge,
+    waiters: Gauge,
+}
+
+impl Gauges {
+    pub(crate) fn new(name: Option<&str>) -> Gauges {
+        let labels = labels(name);
+        Gauges {
+            connections: metrics::gauge!("bb8.connections", labels.clone()),
+            idle: metrics::gauge!("bb8.idle", labels.clone()),
+            waiters: metrics::gauge!("bb8.waiters", labels),
+        }
+    }
+
+    pub(crate) fn publish<C: Send>(&self, internals: &PoolInternals<C>) {
+        self.connections.set(internals.num_conns);
+        self.idle.set(internals.conns.len() as u32);
+        self.waiters.set(internals.waiters.len() as u32);
+    }
+}
+
+// Counts the events reported to a pool's observer, before passing them on to
+// it.
+pub(crate) struct MetricsObserver {
+    observer: Box<dyn PoolObserver>,
+    checkout_duration: Histogram,
+    connects: Counter,
+    connect_errors: Counter,
+    timeouts: Counter,
+}
+
+impl MetricsObserver {
+    pub(crate) fn new(name: Option<&str>, observer: Box<dyn PoolObserver>) -> MetricsObserver {
+        let labels = labels(name);
+        MetricsObserver {
+            observer,
+            checkout_duration: metrics::histogram!("bb8.checkout_duration", labels.clone()),
+            connects: metrics::counter!("bb8.connects", labels.clone()),
+            connect_errors: metrics::counter!("bb8.connect_errors", labels.clone()),
+            timeouts: metrics::counter!("bb8.timeouts", labels),
+        }
+    }
+}
+
+// The wrapper is an implementation detail, so it shows as the observer it
+// wraps.
+impl fmt::Debug for MetricsObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.observer.fmt(f)
+    }
+}
+
+impl PoolObserver for MetricsObserver {
+    fn on_acquire(&self, wait: Duration) {
+        self.checkout_duration.record(wait);
+        self.observer.on_acquire(wait);
+    }
+
+    fn on_release(&self) {
+        self.observer.on_release();
+    }
+
+    fn on_connect(&self) {
+        self.connects.increment(1);
+        self.observer.on_connect();
+    }
+
+    fn on_connect_error(&self) {
+        self.connect_errors.increment(1);
+        self.observer.on_connect_error();
+    }
+
+    fn on_timeout(&self) {
+        self.timeouts.increment(1);
+        self.observer.on_timeout();
+    }
+
+    fn on_reap(&self, count: u32) {
+        self.observer.on_reap(count);
+    }
+
+    fn on_min_idle_unmet(&self, idle: u32, min_idle: u32, unmet_for: Duration) {
+        self.observer.on_min_idle_unmet(idle, min_idle, unmet_for);
+    }
+}
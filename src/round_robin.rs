@@ -0,0 +1,188 @@
+//! A `ManageConnection` that load balances across several backends.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::prelude::*;
+
+use {ConnectInfo, ManageConnection};
+
+/// A connection manager that opens connections from each of several managers
+/// in turn, e.g. to spread a pool's connections across read replicas.
+///
+/// Connections remember which manager opened them, so validation and breakage
+/// checks are dispatched to that manager.
+#[derive(Debug)]
+pub struct RoundRobin<M> {
+    managers: Vec<M>,
+    next: AtomicUsize,
+    // The number of open connections from each manager.
+    counts: Arc<Vec<AtomicU32>>,
+}
+
+impl<M> RoundRobin<M>
+where
+    M: ManageConnection,
+{
+    /// Create a new `RoundRobin` over the given managers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `managers` is empty.
+    pub fn new(managers: Vec<M>) -> RoundRobin<M> {
+        assert!(
+            !managers.is_empty(),
+            "RoundRobin requires at least one manager!"
+        );
+        let counts = managers.iter().map(|_| AtomicU32::new(0)).collect();
+        RoundRobin {
+            managers,
+            next: AtomicUsize::new(0),
+            counts: Arc::new(counts),
+        }
+    }
+
+    /// Returns the managers, in the order connections are opened from them.
+    pub fn managers(&self) -> &[M] {
+        &self.managers
+    }
+
+    /// Returns the number of open connections from each manager, indexed in
+    /// the same order as `managers`.
+    pub fn connections_per_backend(&self) -> Vec<u32> {
+        self.counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+}
+
+/// A connection opened by a `RoundRobin` manager.
+///
+/// This dereferences to the underlying connection.
+#[derive(Debug)]
+pub struct RoundRobinConnection<C> {
+    conn: C,
+    backend: Backend,
+}
+
+impl<C> RoundRobinConnection<C> {
+    /// Returns the index of the manager that opened this connection.
+    pub fn backend(&self) -> usize {
+        self.backend.index
+    }
+}
+
+impl<C> Deref for RoundRobinConnection<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.conn
+    }
+}
+
+impl<C> DerefMut for RoundRobinConnection<C> {
+    fn deref_mut(&mut self) -> &mut C {
+        &mut self.conn
+    }
+}
+
+// Keeps the per-backend connection count up to date.
+#[derive(Debug)]
+struct Backend {
+    index: usize,
+    counts: Arc<Vec<AtomicU32>>,
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        self.counts[self.index].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<M> ManageConnection for RoundRobin<M>
+where
+    M: ManageConnection,
+{
+    type Connection = RoundRobinConnection<M::Connection>;
+    type Error = M::Error;
+
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+        Box::new(self.connect_with_info().map(|(conn, _)| conn))
+    }
+
+    fn connect_with_info(
+        &self,
+    ) -> Box<dyn Future<Item = (Self::Connection, ConnectInfo), Error = Self::Error> + Send> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.managers.len();
+        let counts = self.counts.clone();
+        Box::new(
+            self.managers[index]
+                .connect_with_info()
+                .map(move |(conn, info)| {
+                    counts[index].fetch_add(1, Ordering::Relaxed);
+                    let conn = RoundRobinConnection {
+                        conn,
+                        backend: Backend { index, counts },
+                    };
+                    (conn, info)
+                }),
+        )
+    }
+
+    fn is_valid(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        let RoundRobinConnection { conn, backend } = conn;
+        Box::new(
+            self.managers[backend.index]
+                .is_valid(conn)
+                .then(move |r| match r {
+                    Ok(conn) => Ok(RoundRobinConnection { conn, backend }),
+                    Err((e, conn)) => Err((e, RoundRobinConnection { conn, backend })),
+                }),
+        )
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        self.managers[conn.backend.index].has_broken(&mut conn.conn)
+    }
+
+    fn is_valid_mut(
+        &self,
+        conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = (), Error = Self::Error> + Send> {
+        self.managers[conn.backend.index].is_valid_mut(&mut conn.conn)
+    }
+
+    fn poll_broken(
+        &self,
+        conn: &mut Self::Connection,
+    ) -> Box<dyn Future<Item = bool, Error = ()> + Send> {
+        self.managers[conn.backend.index].poll_broken(&mut conn.conn)
+    }
+
+    fn recycle(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        let RoundRobinConnection { conn, backend } = conn;
+        Box::new(
+            self.managers[backend.index]
+                .recycle(conn)
+                .then(move |r| match r {
+                    Ok(conn) => Ok(RoundRobinConnection { conn, backend }),
+                    Err((e, conn)) => Err((e, RoundRobinConnection { conn, backend })),
+                }),
+        )
+    }
+
+    fn on_pool_drop(&self, conn: Self::Connection) {
+        let RoundRobinConnection { conn, backend } = conn;
+        self.managers[backend.index].on_pool_drop(conn);
+    }
+}
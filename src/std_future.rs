@@ -0,0 +1,39 @@
+//! `std::future::Future` based versions of the checkout APIs, for use with
+//! async/await. Enabled by the `std-future` feature.
+
+use std::future::Future as StdFuture;
+
+use futures03::compat::{Compat, Future01CompatExt};
+
+use {ManageConnection, Pool, PooledConnection, RunError};
+
+impl<M: ManageConnection> Pool<M> {
+    /// Retrieves a connection from the pool.
+    ///
+    /// This is the same as `get`, but returns a `std::future::Future` that
+    /// can be awaited directly.
+    pub fn get_async(
+        &self,
+    ) -> impl StdFuture<Output = Result<PooledConnection<M>, RunError<M::Error>>> + Send {
+        self.get().compat()
+    }
+
+    /// Run a closure returning a `std::future::Future` with a `Connection`.
+    ///
+    /// This is the same as `run`, but both the closure's future and the
+    /// returned future are `std::future::Future`s, so an async block can be
+    /// used for the former and the latter can be awaited directly.
+    pub fn run_async<'a, T, E, U, F>(
+        &self,
+        f: F,
+    ) -> impl StdFuture<Output = Result<T, RunError<E>>> + Send + 'a
+    where
+        F: FnOnce(M::Connection) -> U + Send + 'a,
+        U: StdFuture<Output = Result<(T, M::Connection), (E, M::Connection)>> + Send + 'a,
+        E: From<M::Error> + Send + 'a,
+        T: Send + 'a,
+    {
+        self.run(move |conn| Compat::new(Box::pin(f(conn))))
+            .compat()
+    }
+}
@@ -0,0 +1,188 @@
+//! In-memory connection managers for testing code that uses a pool, without a
+//! database. Enabled by the `testing` feature.
+
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::future::{err, ok};
+use futures::prelude::*;
+use tokio_timer::Delay;
+
+use ManageConnection;
+
+/// The connection opened by the managers in this module.
+#[derive(Debug)]
+pub struct TestConnection {
+    id: u64,
+}
+
+impl TestConnection {
+    /// Returns the connection's id, counting up from 0 in the order the
+    /// manager opened connections.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+/// The error returned by the managers in this module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestError;
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Simulated failure in bb8's test manager")
+    }
+}
+
+impl error::Error for TestError {}
+
+/// A connection manager whose connections always work, unless told
+/// otherwise.
+///
+/// Failures can be switched on and off while the manager is in use through
+/// `Pool::manager`, e.g. to exercise connection retries, the circuit breaker
+/// or the handling of broken connections.
+#[derive(Debug, Default)]
+pub struct OkManager {
+    connect_delay: Option<Duration>,
+    connect_fails: AtomicBool,
+    fail_next_connects: AtomicU32,
+    invalid: AtomicBool,
+    broken: AtomicBool,
+    attempts: AtomicU64,
+    created: AtomicU64,
+}
+
+impl OkManager {
+    /// Creates a new `OkManager`.
+    pub fn new() -> OkManager {
+        Default::default()
+    }
+
+    /// Sets how long opening a connection takes.
+    ///
+    /// This uses the tokio timer, so connections must then be opened within
+    /// the runtime.
+    ///
+    /// Defaults to no delay.
+    pub fn connect_delay(mut self, connect_delay: Duration) -> OkManager {
+        self.connect_delay = Some(connect_delay);
+        self
+    }
+
+    /// Sets whether attempts to open a connection fail.
+    pub fn set_connect_fails(&self, connect_fails: bool) {
+        self.connect_fails.store(connect_fails, Ordering::SeqCst);
+    }
+
+    /// Makes the next `n` attempts to open a connection fail.
+    pub fn fail_next_connects(&self, n: u32) {
+        self.fail_next_connects.store(n, Ordering::SeqCst);
+    }
+
+    /// Sets whether `is_valid` fails for every connection.
+    pub fn set_invalid(&self, invalid: bool) {
+        self.invalid.store(invalid, Ordering::SeqCst);
+    }
+
+    /// Sets whether every connection is reported as broken by `has_broken`.
+    pub fn set_broken(&self, broken: bool) {
+        self.broken.store(broken, Ordering::SeqCst);
+    }
+
+    /// Returns the number of attempts to open a connection, including
+    /// failed ones.
+    pub fn connect_attempts(&self) -> u64 {
+        self.attempts.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of connections opened.
+    pub fn connections_created(&self) -> u64 {
+        self.created.load(Ordering::SeqCst)
+    }
+
+    fn should_fail(&self) -> bool {
+        let countdown =
+            self.fail_next_connects
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        countdown.is_ok() || self.connect_fails.load(Ordering::SeqCst)
+    }
+}
+
+impl ManageConnection for OkManager {
+    type Connection = TestConnection;
+    type Error = TestError;
+
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        let result = if self.should_fail() {
+            Err(TestError)
+        } else {
+            let id = self.created.fetch_add(1, Ordering::SeqCst);
+            Ok(TestConnection { id })
+        };
+        match self.connect_delay {
+            Some(delay) => Box::new(Delay::new(Instant::now() + delay).then(move |_| result)),
+            None => Box::new(result.into_future()),
+        }
+    }
+
+    fn is_valid(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        if self.invalid.load(Ordering::SeqCst) {
+            Box::new(err((TestError, conn)))
+        } else {
+            Box::new(ok(conn))
+        }
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        self.broken.load(Ordering::SeqCst)
+    }
+}
+
+/// A connection manager that always fails to open connections, e.g. to test
+/// how code copes with the database being down.
+#[derive(Debug, Default)]
+pub struct BrokenManager {
+    attempts: AtomicU64,
+}
+
+impl BrokenManager {
+    /// Creates a new `BrokenManager`.
+    pub fn new() -> BrokenManager {
+        Default::default()
+    }
+
+    /// Returns the number of attempts to open a connection.
+    pub fn connect_attempts(&self) -> u64 {
+        self.attempts.load(Ordering::SeqCst)
+    }
+}
+
+impl ManageConnection for BrokenManager {
+    type Connection = TestConnection;
+    type Error = TestError;
+
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        Box::new(err(TestError))
+    }
+
+    fn is_valid(
+        &self,
+        conn: Self::Connection,
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
+        Box::new(err((TestError, conn)))
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        true
+    }
+}
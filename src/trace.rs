@@ -0,0 +1,133 @@
+//! Spans around checkouts and opening connections, recorded through the
+//! `tracing` crate with the `tracing` feature. Without it these do nothing.
+
+#[cfg(feature = "tracing")]
+use std::fmt;
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+use futures::prelude::*;
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+
+use {ManageConnection, RunError, SharedPool};
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+// Identifies a pool in its spans: by its name, or else by its address.
+#[cfg(feature = "tracing")]
+struct PoolId<'a, M: ManageConnection>(&'a SharedPool<M>);
+
+#[cfg(feature = "tracing")]
+impl<'a, M: ManageConnection> fmt::Display for PoolId<'a, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.statics.name {
+            Some(ref name) => f.write_str(name),
+            None => write!(f, "{:p}", self.0),
+        }
+    }
+}
+
+// The span of a checkout. `path` is set to how the checkout was served, see
+// `record_path`.
+#[cfg(feature = "tracing")]
+pub(crate) fn checkout_span<M: ManageConnection>(pool: &SharedPool<M>) -> Span {
+    tracing::debug_span!(
+        "bb8.get",
+        pool = %PoolId(pool),
+        path = Empty,
+        elapsed_ms = Empty,
+        outcome = Empty,
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn checkout_span<M: ManageConnection>(_pool: &SharedPool<M>) -> Span {
+    Span
+}
+
+// The span of opening a connection, including any retries. Created by the
+// caller so that a connection opened for a checkout is traced within it.
+#[cfg(feature = "tracing")]
+pub(crate) fn connect_span<M: ManageConnection>(pool: &SharedPool<M>) -> Span {
+    tracing::debug_span!(
+        "bb8.connect",
+        pool = %PoolId(pool),
+        elapsed_ms = Empty,
+        outcome = Empty,
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn connect_span<M: ManageConnection>(_pool: &SharedPool<M>) -> Span {
+    Span
+}
+
+// Record how the current checkout is served: "idle" by an idle connection,
+// "opened" by waiting with a connection being opened for it, or "queued" by
+// waiting for a connection to be returned.
+#[cfg(feature = "tracing")]
+pub(crate) fn record_path(path: &'static str) {
+    Span::current().record("path", path);
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_path(_path: &'static str) {}
+
+// Run `future` within `span`, recording how long it took and its outcome once
+// it resolves.
+pub(crate) fn instrument<F, E>(span: Span, future: F) -> Traced<F>
+where
+    F: Future<Error = RunError<E>>,
+{
+    Traced {
+        future,
+        span,
+        #[cfg(feature = "tracing")]
+        start: Instant::now(),
+    }
+}
+
+pub(crate) struct Traced<F> {
+    future: F,
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    span: Span,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl<F, E> Future for Traced<F>
+where
+    F: Future<Error = RunError<E>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    #[cfg(feature = "tracing")]
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let _entered = self.span.enter();
+        let r = self.future.poll();
+        let outcome = match r {
+            Ok(Async::NotReady) => return r,
+            Ok(Async::Ready(_)) => "ok",
+            Err(RunError::User(_)) => "error",
+            Err(RunError::TimedOut) => "timed_out",
+            Err(RunError::CircuitOpen) => "circuit_open",
+            Err(RunError::PoolClosed) => "pool_closed",
+            Err(RunError::QueueFull) => "queue_full",
+        };
+        let elapsed = self.start.elapsed();
+        self.span.record("elapsed_ms", elapsed.as_millis() as u64);
+        self.span.record("outcome", outcome);
+        r
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        self.future.poll()
+    }
+}
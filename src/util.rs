@@ -1,3 +1,10 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use futures::executor::{self, Notify, NotifyHandle};
+use futures::{Async, Future};
+
 pub trait Partition2Ext: Iterator {
     fn partition2<B, C, F>(self, f: F) -> (B, C)
     where
@@ -29,3 +36,30 @@ impl<I: Iterator> Partition2Ext for I {
         (left, right)
     }
 }
+
+// Pick a pseudo-random duration between zero and `max`, inclusive.
+pub fn random_duration(max: Duration) -> Duration {
+    // Every `RandomState` is keyed differently, so even an empty hash is unpredictable.
+    let r = RandomState::new().build_hasher().finish();
+    let max = max.as_nanos() as u64;
+    Duration::from_nanos(r % max.saturating_add(1))
+}
+
+struct NopNotify;
+
+impl Notify for NopNotify {
+    fn notify(&self, _: usize) {}
+}
+
+static NOP_NOTIFY: NopNotify = NopNotify;
+
+// Poll a future once, outside of any task. Returns its result if it has already
+// completed, or gives the future back so it can be driven to completion.
+pub fn poll_now<F: Future>(f: F) -> Result<Result<F::Item, F::Error>, F> {
+    let mut spawn = executor::spawn(f);
+    match spawn.poll_future_notify(&NotifyHandle::from(&NOP_NOTIFY), 0) {
+        Ok(Async::Ready(item)) => Ok(Ok(item)),
+        Ok(Async::NotReady) => Err(spawn.into_inner()),
+        Err(e) => Ok(Err(e)),
+    }
+}
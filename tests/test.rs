@@ -1,17 +1,25 @@
 extern crate bb8;
 extern crate futures;
+#[cfg(feature = "std-future")]
+extern crate futures03;
+#[cfg(feature = "metrics")]
+extern crate metrics;
 extern crate tokio;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "tracing")]
+extern crate tracing_core;
 
 use bb8::*;
 
 use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use std::{error, fmt, mem};
+use std::{error, fmt, mem, panic};
 
-use futures::future::{empty, err, join_all, lazy, ok};
+use futures::future::{empty, err, join_all, lazy, loop_fn, ok, Loop};
 use futures::prelude::*;
 use futures::stream::FuturesUnordered;
 use futures::sync::oneshot;
@@ -54,14 +62,15 @@ where
     type Connection = C;
     type Error = Error;
 
-    fn connect(&self) -> Box<Future<Item = Self::Connection, Error = Self::Error> + Send> {
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
         Box::new(ok(Default::default()))
     }
 
     fn is_valid(
         &self,
         conn: Self::Connection,
-    ) -> Box<Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send> {
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
         Box::new(ok(conn))
     }
 
@@ -91,7 +100,7 @@ where
     type Connection = C;
     type Error = Error;
 
-    fn connect(&self) -> Box<Future<Item = Self::Connection, Error = Self::Error> + Send> {
+    fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
         let mut n = self.n.lock().unwrap();
         if *n > 0 {
             *n -= 1;
@@ -104,7 +113,8 @@ where
     fn is_valid(
         &self,
         conn: Self::Connection,
-    ) -> Box<Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send> {
+    ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+    {
         Box::new(ok(conn))
     }
 
@@ -222,6 +232,54 @@ fn test_acquire_release() {
 fn test_is_send_sync() {
     fn is_send_sync<T: Send + Sync>() {}
     is_send_sync::<Pool<OkManager<FakeConnection>>>();
+    is_send_sync::<PooledConnection<OkManager<FakeConnection>>>();
+}
+
+#[test]
+fn test_builder_validate() {
+    let builder = || Pool::<OkManager<FakeConnection>>::builder();
+    assert_eq!(builder().validate(), Ok(()));
+    assert_eq!(
+        builder().max_size(0).validate(),
+        Err(BuilderError::Zero("max_size"))
+    );
+    assert_eq!(
+        builder().max_size(2).min_idle(Some(3)).validate(),
+        Err(BuilderError::ExceedsMaxSize("min_idle"))
+    );
+    assert_eq!(
+        builder().min_connections(Some(11)).validate(),
+        Err(BuilderError::ExceedsMaxSize("min_connections"))
+    );
+    assert_eq!(
+        builder()
+            .connection_timeout(Duration::from_secs(0))
+            .validate(),
+        Err(BuilderError::Zero("connection_timeout"))
+    );
+    assert_eq!(
+        builder()
+            .connection_retry(1, Duration::from_secs(2), Duration::from_secs(1))
+            .validate(),
+        Err(BuilderError::RetryDelay)
+    );
+    let e = builder()
+        .adaptive_idle(Duration::from_secs(60), 101.0)
+        .validate()
+        .unwrap_err();
+    assert_eq!(e, BuilderError::TargetPercentile(101.0));
+    assert_eq!(
+        e.to_string(),
+        "target_percentile must be between 0 and 100, not 101"
+    );
+
+    // Building with an invalid configuration panics.
+    let result = panic::catch_unwind(|| {
+        builder()
+            .reaper_rate(Duration::from_secs(0))
+            .build_unchecked(OkManager::<FakeConnection>::new())
+    });
+    assert!(result.is_err());
 }
 
 #[test]
@@ -243,14 +301,14 @@ fn test_drop_on_broken() {
         type Connection = Connection;
         type Error = Error;
 
-        fn connect(&self) -> Box<Future<Item = Self::Connection, Error = Self::Error> + Send> {
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
             Box::new(ok(Default::default()))
         }
 
         fn is_valid(
             &self,
             conn: Self::Connection,
-        ) -> Box<Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
         {
             Box::new(ok(conn))
         }
@@ -293,6 +351,36 @@ fn test_initialization_failure() {
     assert_eq!(e.unwrap_err(), Error);
 }
 
+#[test]
+fn test_build_with_result() {
+    let mut event_loop = Runtime::new().unwrap();
+    let (pool, result) = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(5)
+                .min_idle(Some(5))
+                .build_with_result(NthConnectionFailManager::<FakeConnection>::new(3))
+        }))
+        .unwrap();
+    let e = result.unwrap_err();
+    assert_eq!(e.wanted, 5);
+    assert!(e.connections < 5);
+    assert_eq!(e.error, Some(Error));
+
+    // The pool is still usable.
+    assert!(event_loop.block_on(pool.get()).is_ok());
+
+    let (_, result) = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(5)
+                .min_idle(Some(5))
+                .build_with_result(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert!(result.is_ok());
+}
+
 #[test]
 fn test_lazy_initialization_failure() {
     let mut event_loop = Runtime::new().unwrap();
@@ -312,7 +400,7 @@ fn test_lazy_initialization_failure() {
         r.into_future()
     }));
     assert!(e.is_err());
-    assert_eq!(e.unwrap_err(), bb8::RunError::TimedOut);
+    assert_eq!(e.unwrap_err(), bb8::RunError::User(Error));
 }
 
 #[test]
@@ -357,6 +445,39 @@ fn test_get_timeout() {
     event_loop.block_on(r.into_future()).unwrap();
 }
 
+#[test]
+fn test_no_connection_timeout() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_timeout(None)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    let (tx, mut rx) = oneshot::channel();
+    event_loop.spawn(pool.get().then(move |r| {
+        tx.send(r.is_ok()).unwrap();
+        Ok(())
+    }));
+
+    // Without a timeout the checkout keeps waiting until the connection is
+    // returned.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(300)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(rx.try_recv(), Ok(None));
+    mem::drop(conn);
+    assert!(event_loop.block_on(rx).unwrap());
+}
+
 #[test]
 fn test_now_invalid() {
     static INVALID: AtomicBool = AtomicBool::new(false);
@@ -367,7 +488,7 @@ fn test_now_invalid() {
         type Connection = FakeConnection;
         type Error = Error;
 
-        fn connect(&self) -> Box<Future<Item = Self::Connection, Error = Self::Error> + Send> {
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
             let r = if INVALID.load(Ordering::SeqCst) {
                 Err(Error)
             } else {
@@ -379,7 +500,7 @@ fn test_now_invalid() {
         fn is_valid(
             &self,
             conn: Self::Connection,
-        ) -> Box<Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
         {
             println!("Called is_valid");
             let r = if INVALID.load(Ordering::SeqCst) {
@@ -531,91 +652,211 @@ fn test_max_lifetime() {
 }
 
 #[test]
-fn test_min_idle() {
+fn test_expire_on_check_out() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
     let mut event_loop = Runtime::new().unwrap();
+    let manager = NthConnectionFailManager::<Connection>::new(5);
     let pool = event_loop
         .block_on(lazy(|| {
             Pool::builder()
-                .max_size(5)
-                .min_idle(Some(2))
-                .build(OkManager::<FakeConnection>::new())
+                .max_lifetime(Some(Duration::from_millis(50)))
+                .expire_on_check_out(true)
+                .reaper(false)
+                .max_size(2)
+                .build(manager)
         }))
         .unwrap();
 
+    let r: Result<(), RunError<Error>> = event_loop.block_on(pool.run(|conn| Ok(((), conn))));
+    r.unwrap();
+    assert_eq!(pool.state().idle_connections, 1);
+
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(100)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    // Without the reaper, the expired connection is still idle.
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // Checking out closes it and opens a new one instead.
+    let r: Result<(), RunError<Error>> = event_loop.block_on(pool.run(|conn| Ok(((), conn))));
+    r.unwrap();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
     let state = pool.state();
-    assert_eq!(2, state.idle_connections);
-    assert_eq!(2, state.connections);
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 1);
+}
 
-    let mut rx = Vec::with_capacity(3);
-    let mut tx = Vec::with_capacity(3);
-    for _ in 0..3 {
-        let (tx1, rx1) = oneshot::channel();
-        let (tx2, rx2) = oneshot::channel();
-        event_loop.spawn(
-            pool.run(|conn| {
-                tx1.send(()).unwrap();
-                rx2.then(|r| match r {
-                    Ok(v) => Ok((v, conn)),
-                    Err(_) => Err((Error, conn)),
-                })
-            })
-            .map_err(|_| ()),
-        );
-        rx.push(rx1);
-        tx.push(tx2);
+#[test]
+fn test_clock() {
+    use std::time::Instant;
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
     }
 
-    event_loop
-        .block_on(FuturesUnordered::from_iter(rx).collect())
-        .unwrap();
-    let state = pool.state();
-    assert_eq!(2, state.idle_connections);
-    assert_eq!(5, state.connections);
+    #[derive(Debug, Clone)]
+    struct ManualClock(Arc<Mutex<Instant>>);
 
-    for tx in tx.into_iter() {
-        tx.send(()).unwrap();
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
     }
 
-    // And wait for the connections to return.
+    let clock = ManualClock(Arc::new(Mutex::new(Instant::now())));
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_lifetime(Some(Duration::from_secs(60 * 60)))
+                .reaper_rate(Duration::from_millis(100))
+                .max_size(2)
+                .min_idle(Some(2))
+                .clock(Box::new(clock.clone()))
+                .build(OkManager::<Connection>::new())
+        }))
+        .unwrap();
+
+    // Real time passing doesn't age the connections.
     assert!(event_loop
         .block_on(lazy(|| Timeout::new(
             empty::<(), ()>(),
-            Duration::from_secs(1)
+            Duration::from_millis(300)
         )))
         .unwrap_err()
         .is_elapsed());
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+    assert_eq!(pool.state().idle_connections, 2);
 
-    let state = pool.state();
-    assert_eq!(5, state.idle_connections);
-    assert_eq!(5, state.connections);
+    // Advancing the clock past the max lifetime does.
+    *clock.0.lock().unwrap() += Duration::from_secs(2 * 60 * 60);
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(300)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.state().idle_connections, 2);
 }
 
 #[test]
-fn test_conns_drop_on_pool_drop() {
-    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+fn test_incremental_reaping() {
+    use std::time::Instant;
 
+    static POOL: Mutex<Option<Pool<OkManager<Connection>>>> = Mutex::new(None);
+    static IDLE_AT_DROP: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    #[derive(Default)]
     struct Connection;
 
     impl Drop for Connection {
         fn drop(&mut self) {
-            DROPPED.fetch_add(1, Ordering::SeqCst);
+            if let Some(pool) = POOL.lock().unwrap().as_ref() {
+                IDLE_AT_DROP
+                    .lock()
+                    .unwrap()
+                    .push(pool.state().idle_connections);
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct ManualClock(Arc<Mutex<Instant>>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
         }
     }
 
+    let clock = ManualClock(Arc::new(Mutex::new(Instant::now())));
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_lifetime(Some(Duration::from_secs(60 * 60)))
+                .reaper_rate(Duration::from_millis(100))
+                .max_size(100)
+                .min_idle(Some(100))
+                .clock(Box::new(clock.clone()))
+                .build(OkManager::<Connection>::new())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 100);
+    event_loop
+        .block_on(lazy(|| {
+            pool.set_min_idle(None);
+            ok::<(), ()>(())
+        }))
+        .unwrap();
+    *POOL.lock().unwrap() = Some(pool.clone());
+
+    *clock.0.lock().unwrap() += Duration::from_secs(2 * 60 * 60);
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(300)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    POOL.lock().unwrap().take();
+    assert_eq!(pool.state().idle_connections, 0);
+
+    // The lock is released between batches, so some connections are closed
+    // while others are still idle, and no batch closes the whole pool.
+    let idle_at_drop = IDLE_AT_DROP.lock().unwrap();
+    assert_eq!(idle_at_drop.len(), 100);
+    assert!(idle_at_drop.iter().all(|&idle| idle < 100));
+    assert!(idle_at_drop.iter().any(|&idle| idle >= 50));
+}
+
+#[test]
+fn test_replenish() {
+    static FAIL: AtomicBool = AtomicBool::new(true);
+
+    #[derive(Debug, Default)]
     struct Handler;
 
     impl ManageConnection for Handler {
-        type Connection = Connection;
+        type Connection = FakeConnection;
         type Error = Error;
 
-        fn connect(&self) -> Box<Future<Item = Self::Connection, Error = Self::Error> + Send> {
-            Box::new(ok(Connection))
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            if FAIL.load(Ordering::SeqCst) {
+                Box::new(err(Error))
+            } else {
+                Box::new(ok(FakeConnection))
+            }
         }
 
         fn is_valid(
             &self,
             conn: Self::Connection,
-        ) -> Box<Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
         {
             Box::new(ok(conn))
         }
@@ -628,32 +869,4390 @@ fn test_conns_drop_on_pool_drop() {
     let mut event_loop = Runtime::new().unwrap();
     let pool = event_loop
         .block_on(lazy(|| {
-            Pool::builder()
-                .max_lifetime(Some(Duration::from_secs(10)))
-                .max_size(10)
-                .min_idle(Some(10))
-                .build(Handler)
+            ok::<_, ()>(
+                Pool::builder()
+                    .max_size(5)
+                    .min_idle(Some(2))
+                    .build_unchecked(Handler),
+            )
         }))
         .unwrap();
 
-    mem::drop(pool);
+    // Let the initial connection attempts fail.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(100)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(pool.state().connections, 0);
 
-    for _ in 0..10 {
-        if DROPPED.load(Ordering::SeqCst) == 10 {
-            return;
+    FAIL.store(false, Ordering::SeqCst);
+    event_loop.block_on(lazy(|| pool.replenish())).unwrap();
+    assert_eq!(pool.state().idle_connections, 2);
+    assert_eq!(pool.state().connections, 2);
+
+    // Already at the minimum, so nothing more is opened.
+    event_loop.block_on(lazy(|| pool.replenish())).unwrap();
+    assert_eq!(pool.state().connections, 2);
+}
+
+#[test]
+fn test_min_idle_unmet() {
+    static FAIL: AtomicBool = AtomicBool::new(true);
+    static UNMET: Mutex<Vec<(u32, u32)>> = Mutex::new(Vec::new());
+
+    #[derive(Debug, Default)]
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            if FAIL.load(Ordering::SeqCst) {
+                Box::new(err(Error))
+            } else {
+                Box::new(ok(FakeConnection))
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    #[derive(Debug)]
+    struct Observer;
+
+    impl PoolObserver for Observer {
+        fn on_min_idle_unmet(&self, idle: u32, min_idle: u32, unmet_for: Duration) {
+            assert!(unmet_for >= Duration::from_millis(200));
+            UNMET.lock().unwrap().push((idle, min_idle));
         }
+    }
 
+    let sleep = |event_loop: &mut Runtime, millis| {
         assert!(event_loop
-            .block_on(lazy(|| Timeout::new(
+            .block_on(lazy(move || Timeout::new(
                 empty::<(), ()>(),
-                Duration::from_secs(1)
+                Duration::from_millis(millis)
             )))
             .unwrap_err()
             .is_elapsed());
+    };
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            ok::<_, ()>(
+                Pool::builder()
+                    .max_size(5)
+                    .min_idle(Some(2))
+                    .reaper_rate(Duration::from_millis(50))
+                    .min_idle_alert_threshold(Duration::from_millis(200))
+                    .observer(Box::new(Observer))
+                    .build_unchecked(Handler),
+            )
+        }))
+        .unwrap();
+
+    // A sustained failure is reported once.
+    sleep(&mut event_loop, 500);
+    assert_eq!(*UNMET.lock().unwrap(), vec![(0, 2)]);
+
+    // Once the pool has recovered, a new failure is reported again.
+    FAIL.store(false, Ordering::SeqCst);
+    event_loop.block_on(lazy(|| pool.replenish())).unwrap();
+    sleep(&mut event_loop, 100);
+    FAIL.store(true, Ordering::SeqCst);
+    // Replacing the closed connections fails.
+    assert!(event_loop
+        .block_on(lazy(|| pool.retain(|_| false)))
+        .is_err());
+    assert_eq!(pool.state().connections, 0);
+    assert_eq!(UNMET.lock().unwrap().len(), 1);
+    sleep(&mut event_loop, 500);
+    assert_eq!(*UNMET.lock().unwrap(), vec![(0, 2), (0, 2)]);
+}
+
+#[test]
+fn test_min_idle_unmet_without_timeouts() {
+    static UNMET: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Observer;
+
+    impl PoolObserver for Observer {
+        fn on_min_idle_unmet(&self, _: u32, _: u32, _: Duration) {
+            UNMET.fetch_add(1, Ordering::SeqCst);
+        }
     }
 
-    panic!(
-        "Timed out waiting for connections to drop, {} dropped",
-        DROPPED.load(Ordering::SeqCst)
-    );
+    let mut event_loop = Runtime::new().unwrap();
+    let _pool = event_loop
+        .block_on(lazy(|| {
+            ok::<_, ()>(
+                Pool::builder()
+                    .max_size(5)
+                    .min_idle(Some(2))
+                    .max_lifetime(None)
+                    .idle_timeout(None)
+                    .reaper_rate(Duration::from_millis(50))
+                    .min_idle_alert_threshold(Duration::from_millis(100))
+                    .observer(Box::new(Observer))
+                    .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0)),
+            )
+        }))
+        .unwrap();
+
+    // The reaper runs to report the failure, even though it has nothing to
+    // reap.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(400)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(UNMET.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_concurrent_replenish() {
+    use tokio::runtime::Runtime;
+
+    let mut runtime = Runtime::new().unwrap();
+    let pool = runtime
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(8)
+                .min_idle(Some(4))
+                .idle_timeout(Some(Duration::from_millis(5)))
+                .max_lifetime(Some(Duration::from_millis(20)))
+                .reaper_rate(Duration::from_millis(5))
+                .connection_timeout(Duration::from_secs(5))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    // Check out connections while the reaper churns them and the pool is
+    // replenished and resized, from many threads at once.
+    let tasks = (0..16).map(|task: u32| {
+        let pool = pool.clone();
+        loop_fn(0, move |i: u32| {
+            let pool = pool.clone();
+            pool.get().then(move |r| {
+                mem::drop(r.unwrap());
+                if i.is_multiple_of(5) {
+                    pool.set_max_size(1 + (i * 7 + task) % 8);
+                }
+                pool.replenish().then(move |_| {
+                    if i == 50 {
+                        Ok::<_, ()>(Loop::Break(()))
+                    } else {
+                        Ok(Loop::Continue(i + 1))
+                    }
+                })
+            })
+        })
+    });
+    runtime
+        .block_on(join_all(tasks.collect::<Vec<_>>()))
+        .unwrap();
+
+    let pool2 = pool.clone();
+    runtime
+        .block_on(lazy(move || {
+            pool2.set_max_size(8);
+            ok::<(), ()>(())
+        }))
+        .unwrap();
+    // The reaper keeps churning the idle connections, so wait for a moment
+    // when none are being opened.
+    let mut settled = false;
+    for _ in 0..50 {
+        assert!(runtime
+            .block_on(lazy(|| Timeout::new(
+                empty::<(), ()>(),
+                Duration::from_millis(10)
+            )))
+            .unwrap_err()
+            .is_elapsed());
+        let state = pool.state();
+        assert!(state.connections <= 8);
+        if state.pending_connections == 0 && state.connections == state.idle_connections {
+            settled = true;
+            break;
+        }
+    }
+    assert!(settled);
+}
+
+#[test]
+fn test_reaper_jitter() {
+    use std::time::Instant;
+
+    // Records when the reaper looks at the time.
+    #[derive(Debug, Clone, Default)]
+    struct RecordingClock(Arc<Mutex<Vec<Instant>>>);
+
+    impl Clock for RecordingClock {
+        fn now(&self) -> Instant {
+            let now = Instant::now();
+            self.0.lock().unwrap().push(now);
+            now
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let built = Instant::now();
+    let clocks = (0..5)
+        .map(|_| {
+            let clock = RecordingClock::default();
+            let builder = Pool::builder()
+                .reaper_rate(Duration::from_millis(200))
+                .reaper_jitter(Duration::from_millis(100))
+                .clock(Box::new(clock.clone()));
+            let pool = event_loop
+                .block_on(lazy(|| builder.build(OkManager::<FakeConnection>::new())))
+                .unwrap();
+            (pool, clock)
+        })
+        .collect::<Vec<_>>();
+
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(350)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    let first_reaps = clocks
+        .iter()
+        .map(|(_, clock)| clock.0.lock().unwrap()[0] - built)
+        .collect::<Vec<_>>();
+    for &first in &first_reaps {
+        assert!(first >= Duration::from_millis(200));
+    }
+    let earliest = first_reaps.iter().min().unwrap();
+    let latest = first_reaps.iter().max().unwrap();
+    assert!(*latest - *earliest > Duration::from_millis(1));
+}
+
+#[test]
+fn test_min_idle() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(5)
+                .min_idle(Some(2))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let state = pool.state();
+    assert_eq!(2, state.idle_connections);
+    assert_eq!(2, state.connections);
+
+    let mut rx = Vec::with_capacity(3);
+    let mut tx = Vec::with_capacity(3);
+    for _ in 0..3 {
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, rx2) = oneshot::channel();
+        event_loop.spawn(
+            pool.run(|conn| {
+                tx1.send(()).unwrap();
+                rx2.then(|r| match r {
+                    Ok(v) => Ok((v, conn)),
+                    Err(_) => Err((Error, conn)),
+                })
+            })
+            .map_err(|_| ()),
+        );
+        rx.push(rx1);
+        tx.push(tx2);
+    }
+
+    event_loop
+        .block_on(FuturesUnordered::from_iter(rx).collect())
+        .unwrap();
+    let state = pool.state();
+    assert_eq!(2, state.idle_connections);
+    assert_eq!(5, state.connections);
+
+    for tx in tx.into_iter() {
+        tx.send(()).unwrap();
+    }
+
+    // And wait for the connections to return.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_secs(1)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    let state = pool.state();
+    assert_eq!(5, state.idle_connections);
+    assert_eq!(5, state.connections);
+}
+
+#[test]
+fn test_lazy_min_idle() {
+    static DOWN: AtomicBool = AtomicBool::new(true);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            if DOWN.load(Ordering::SeqCst) {
+                Box::new(err(Error))
+            } else {
+                Box::new(ok(FakeConnection))
+            }
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    // The pool builds even though the database is down.
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(5)
+                .min_idle(Some(2))
+                .lazy_min_idle(true)
+                .build(Handler)
+        }))
+        .unwrap();
+    assert_eq!(pool.state().connections, 0);
+
+    // The minimum is maintained once a checkout succeeds.
+    DOWN.store(false, Ordering::SeqCst);
+    let _conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    let state = pool.state();
+    assert_eq!(state.idle_connections, 2);
+    assert_eq!(state.connections, 3);
+}
+
+#[test]
+fn test_adaptive_idle() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(10)
+                .idle_timeout(Some(Duration::from_millis(100)))
+                .reaper_rate(Duration::from_millis(50))
+                .adaptive_idle(Duration::from_millis(600), 100.0)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let sleep = |event_loop: &mut Runtime, millis| {
+        assert!(event_loop
+            .block_on(lazy(move || Timeout::new(
+                empty::<(), ()>(),
+                Duration::from_millis(millis)
+            )))
+            .unwrap_err()
+            .is_elapsed());
+    };
+
+    // A burst of demand...
+    let conns = (0..4)
+        .map(|_| event_loop.block_on(pool.get()).unwrap())
+        .collect::<Vec<_>>();
+    sleep(&mut event_loop, 150);
+    mem::drop(conns);
+
+    // ...keeps its connections open past their idle timeout...
+    sleep(&mut event_loop, 300);
+    assert_eq!(pool.state().idle_connections, 4);
+
+    // ...until it drops out of the window.
+    sleep(&mut event_loop, 800);
+    assert_eq!(pool.state().idle_connections, 0);
+}
+
+#[test]
+fn test_conns_drop_on_pool_drop() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_lifetime(Some(Duration::from_secs(10)))
+                .max_size(10)
+                .min_idle(Some(10))
+                .build(Handler)
+        }))
+        .unwrap();
+
+    mem::drop(pool);
+
+    for _ in 0..10 {
+        if DROPPED.load(Ordering::SeqCst) == 10 {
+            return;
+        }
+
+        assert!(event_loop
+            .block_on(lazy(|| Timeout::new(
+                empty::<(), ()>(),
+                Duration::from_secs(1)
+            )))
+            .unwrap_err()
+            .is_elapsed());
+    }
+
+    panic!(
+        "Timed out waiting for connections to drop, {} dropped",
+        DROPPED.load(Ordering::SeqCst)
+    );
+}
+
+#[test]
+fn test_guard() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_millis(100))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    let state = pool.state();
+    assert_eq!(1, state.connections);
+    assert_eq!(0, state.idle_connections);
+
+    // The only connection is checked out, so this has to time out.
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::TimedOut);
+
+    // Dropping the guard returns the connection to the pool.
+    mem::drop(conn);
+    let state = pool.state();
+    assert_eq!(1, state.connections);
+    assert_eq!(1, state.idle_connections);
+
+    assert!(event_loop.block_on(pool.get()).is_ok());
+}
+
+#[test]
+fn test_wait_stats() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    let state = pool.state();
+    assert_eq!(0, state.wait_count);
+
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(
+        pool.get()
+            .map(move |conn| {
+                mem::drop(conn);
+                tx.send(()).unwrap();
+            })
+            .map_err(|_| ()),
+    );
+
+    // Let the second checkout wait for a bit.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(100)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    mem::drop(conn);
+    event_loop.block_on(rx).unwrap();
+
+    let state = pool.state();
+    assert_eq!(1, state.wait_count);
+    assert!(state.total_wait >= Duration::from_millis(100));
+    assert_eq!(state.total_wait, state.max_wait);
+}
+
+#[test]
+fn test_connection_stats() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(3)
+                .min_idle(Some(2))
+                .connection_timeout(Duration::from_millis(100))
+                .build(NthConnectionFailManager::<FakeConnection>::new(2))
+        }))
+        .unwrap();
+
+    let state = pool.state();
+    assert_eq!(state.connections_created, 2);
+    assert_eq!(state.connection_errors, 0);
+
+    let _conn1 = event_loop.block_on(pool.get()).unwrap();
+    let _conn2 = event_loop.block_on(pool.get()).unwrap();
+    assert!(event_loop.block_on(pool.get()).is_err());
+
+    let state = pool.state();
+    assert_eq!(state.connections_created, 2);
+    assert!(state.connection_errors >= 1);
+}
+
+#[test]
+fn test_reset_stats() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(2))
+                .connection_timeout(Duration::from_millis(100))
+                .build(NthConnectionFailManager::<FakeConnection>::new(2))
+        }))
+        .unwrap();
+
+    let _conn1 = event_loop.block_on(pool.get()).unwrap();
+    let conn2 = event_loop.block_on(pool.get()).unwrap();
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(
+        pool.get()
+            .map(move |conn| tx.send(conn).unwrap())
+            .map_err(|_| ()),
+    );
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(10)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    mem::drop(conn2);
+    let _conn2 = event_loop.block_on(rx).unwrap();
+    let state = pool.state();
+    assert_eq!(state.connections_created, 2);
+    assert_eq!(state.wait_count, 1);
+
+    pool.reset_stats();
+    let state = pool.state();
+    assert_eq!(state.wait_count, 0);
+    assert_eq!(state.total_wait, Duration::from_secs(0));
+    assert_eq!(state.max_wait, Duration::from_secs(0));
+    assert_eq!(state.connections_created, 0);
+    assert_eq!(state.connection_errors, 0);
+    // The live fields are unaffected.
+    assert_eq!(state.connections, 2);
+    assert_eq!(state.idle_connections, 0);
+}
+
+#[test]
+fn test_pending_state() {
+    #[derive(Debug, Default)]
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(empty())
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            ok::<_, ()>(Pool::builder().max_size(2).build_unchecked(Handler))
+        }))
+        .unwrap();
+
+    for _ in 0..3 {
+        event_loop.spawn(pool.get().then(|_| Ok(())));
+    }
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(100)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    let state = pool.state();
+    assert_eq!(state.connections, 0);
+    assert_eq!(state.pending_connections, 2);
+    assert_eq!(state.waiters, 3);
+}
+
+#[test]
+fn test_connection_customizer() {
+    static ACQUIRED: AtomicUsize = AtomicUsize::new(0);
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+    static FAIL: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Debug)]
+    struct Customizer;
+
+    impl ConnectionCustomizer<FakeConnection, Error> for Customizer {
+        fn on_acquire(
+            &self,
+            _: &mut FakeConnection,
+        ) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+            if FAIL.load(Ordering::SeqCst) {
+                return Box::new(err(Error));
+            }
+            ACQUIRED.fetch_add(1, Ordering::SeqCst);
+            Box::new(ok(()))
+        }
+
+        fn on_release(&self, _: &mut FakeConnection) {
+            RELEASED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_customizer(Box::new(Customizer))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(ACQUIRED.load(Ordering::SeqCst), 1);
+    assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+    mem::drop(conn);
+    assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+
+    event_loop
+        .block_on(pool.run(|conn| Ok::<_, (Error, _)>(((), conn))))
+        .unwrap();
+    assert_eq!(ACQUIRED.load(Ordering::SeqCst), 2);
+    assert_eq!(RELEASED.load(Ordering::SeqCst), 2);
+
+    // Dedicated connections are customized too, unless asked for raw.
+    event_loop.block_on(pool.dedicated_connection()).unwrap();
+    assert_eq!(ACQUIRED.load(Ordering::SeqCst), 3);
+    event_loop
+        .block_on(pool.dedicated_connection_raw())
+        .unwrap();
+    assert_eq!(ACQUIRED.load(Ordering::SeqCst), 3);
+
+    // A failed `on_acquire` drops the connection and fails the checkout.
+    FAIL.store(true, Ordering::SeqCst);
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::User(Error));
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[test]
+fn test_try_get() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.try_get()).unwrap();
+    assert!(conn.is_some());
+
+    // The only connection is checked out, so there is nothing to hand back.
+    assert!(event_loop.block_on(pool.try_get()).unwrap().is_none());
+    assert_eq!(pool.state().connections, 1);
+
+    mem::drop(conn);
+    assert!(event_loop.block_on(pool.try_get()).unwrap().is_some());
+}
+
+#[test]
+fn test_connections_stream() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .connection_timeout(Duration::from_millis(100))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conns = event_loop
+        .block_on(pool.connections().take(2).collect())
+        .unwrap();
+    assert_eq!(conns.len(), 2);
+    assert_eq!(pool.state().idle_connections, 0);
+
+    // The pool is exhausted, so the next connection isn't yielded until one
+    // is returned.
+    match event_loop.block_on(pool.connections().into_future()) {
+        Err((RunError::TimedOut, _)) => {}
+        _ => panic!("expected the checkout to time out"),
+    }
+    mem::drop(conns);
+    let (conn, _) = event_loop
+        .block_on(pool.connections().into_future())
+        .map_err(|(e, _)| e)
+        .unwrap();
+    assert!(conn.is_some());
+}
+
+#[test]
+fn test_get_timeout_override() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_secs(30))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+
+    let e = event_loop
+        .block_on(pool.get_timeout(Duration::from_millis(100)))
+        .unwrap_err();
+    assert_eq!(e, RunError::TimedOut);
+
+    let e = event_loop
+        .block_on(pool.run_timeout(Duration::from_millis(100), |conn| {
+            Ok::<_, (Error, _)>(((), conn))
+        }))
+        .unwrap_err();
+    assert_eq!(e, RunError::TimedOut);
+
+    mem::drop(conn);
+    assert!(event_loop
+        .block_on(pool.get_timeout(Duration::from_millis(100)))
+        .is_ok());
+}
+
+#[test]
+fn test_with_connection_timeout() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_secs(30))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    let fast = pool.with_connection_timeout(Duration::from_millis(100));
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+
+    let e = event_loop.block_on(fast.get()).unwrap_err();
+    assert_eq!(e, RunError::TimedOut);
+
+    let e = event_loop
+        .block_on(fast.clone().run(|conn| Ok::<_, (Error, _)>(((), conn))))
+        .unwrap_err();
+    assert_eq!(e, RunError::TimedOut);
+
+    // The handles share the pool's connections.
+    mem::drop(conn);
+    let conn = event_loop.block_on(fast.get()).unwrap();
+    assert_eq!(pool.state().idle_connections, 0);
+    mem::drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[test]
+fn test_weak_pool() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    let weak = pool.downgrade();
+    let upgraded = weak.upgrade().unwrap();
+    event_loop.block_on(upgraded.get()).unwrap();
+    mem::drop(upgraded);
+
+    // Upgrading a reserved handle keeps using the reservation.
+    let reserved = pool.with_reservation(1);
+    let weak_reserved = reserved.downgrade();
+    assert_eq!(pool.available_permits(), 1);
+    assert_eq!(weak_reserved.upgrade().unwrap().available_permits(), 2);
+    mem::drop(reserved);
+    assert!(weak_reserved.upgrade().is_none());
+
+    // Weak handles don't keep the pool alive.
+    mem::drop(pool);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn test_get_timeout_bounds_validation() {
+    use std::time::Instant;
+    use tokio::timer::Delay;
+
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Connection(usize);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection(CONNECTED.fetch_add(1, Ordering::SeqCst))))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            // The initial connections are all stale, and slow to find out.
+            let stale = conn.0 < 10;
+            Box::new(
+                Delay::new(Instant::now() + Duration::from_millis(50)).then(move |_| {
+                    if stale {
+                        Err((Error, conn))
+                    } else {
+                        Ok(conn)
+                    }
+                }),
+            )
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(10)
+                .min_idle(Some(10))
+                .connection_timeout(Duration::from_millis(200))
+                .build(Handler)
+        }))
+        .unwrap();
+
+    let start = Instant::now();
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::TimedOut);
+    assert!(start.elapsed() < Duration::from_millis(400));
+}
+
+#[test]
+fn test_connection_retry() {
+    static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+    static SUNK: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Clone, Copy)]
+    struct CountingSink;
+
+    impl ErrorSink<Error> for CountingSink {
+        fn sink(&self, _: Error) {
+            SUNK.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn boxed_clone(&self) -> Box<dyn ErrorSink<Error>> {
+            Box::new(*self)
+        }
+    }
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            if ATTEMPTS.fetch_add(1, Ordering::SeqCst) < 2 {
+                Box::new(err(Error))
+            } else {
+                Box::new(ok(FakeConnection))
+            }
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .connection_retry(2, Duration::from_millis(10), Duration::from_millis(50))
+                .error_sink(Box::new(CountingSink))
+                .build(Handler)
+        }))
+        .unwrap();
+
+    assert_eq!(ATTEMPTS.load(Ordering::SeqCst), 3);
+    assert_eq!(SUNK.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let mut event_loop = Runtime::new().unwrap();
+    let manager = NthConnectionFailManager::<FakeConnection>::new(0);
+    let pool = event_loop
+        .block_on(lazy(|| {
+            ok::<_, ()>(
+                Pool::builder()
+                    .max_size(1)
+                    .min_idle(Some(1))
+                    .connection_timeout(Duration::from_millis(100))
+                    .circuit_breaker(1, Duration::from_millis(300))
+                    .build_unchecked(manager),
+            )
+        }))
+        .unwrap();
+
+    // Let the initial connection attempt fail.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::CircuitOpen);
+
+    // After the cooldown a single probe is let through.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(300)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_ne!(e, RunError::CircuitOpen);
+
+    // The probe failed, so the circuit is open again.
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::CircuitOpen);
+}
+
+#[test]
+fn test_shutdown() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(2))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn1 = event_loop.block_on(pool.get()).unwrap();
+    let conn2 = event_loop.block_on(pool.get()).unwrap();
+
+    // Queue up a waiter behind the checked out connections.
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(pool.get().then(move |r| {
+        tx.send(r.map(|_| ())).unwrap();
+        Ok(())
+    }));
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    event_loop.block_on(pool.shutdown()).unwrap();
+    assert_eq!(event_loop.block_on(rx).unwrap(), Err(RunError::PoolClosed));
+    let state = pool.state();
+    assert_eq!(2, state.connections);
+    assert_eq!(0, state.idle_connections);
+
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::PoolClosed);
+
+    // Checked out connections are closed rather than re-queued.
+    mem::drop(conn1);
+    mem::drop(conn2);
+    let state = pool.state();
+    assert_eq!(0, state.connections);
+    assert_eq!(0, state.idle_connections);
+}
+
+#[test]
+fn test_closed_entry_points() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(1))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert!(!pool.is_closed());
+
+    event_loop.block_on(pool.shutdown()).unwrap();
+    assert!(pool.is_closed());
+
+    let r = event_loop.block_on(pool.run(|conn| ok::<_, (Error, _)>(((), conn))));
+    assert_eq!(r.unwrap_err(), RunError::PoolClosed);
+    assert_eq!(
+        event_loop.block_on(pool.get()).unwrap_err(),
+        RunError::PoolClosed
+    );
+    assert_eq!(
+        event_loop.block_on(pool.try_get()).unwrap_err(),
+        RunError::PoolClosed
+    );
+    assert_eq!(
+        event_loop
+            .block_on(pool.dedicated_connection())
+            .unwrap_err(),
+        RunError::PoolClosed
+    );
+    assert_eq!(
+        event_loop
+            .block_on(pool.dedicated_connection_raw())
+            .unwrap_err(),
+        RunError::PoolClosed
+    );
+    assert_eq!(
+        event_loop.block_on(pool.replenish()).unwrap_err(),
+        RunError::PoolClosed
+    );
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[test]
+fn test_cancel_checkouts() {
+    use std::time::Instant;
+    use tokio::timer::Delay;
+
+    #[derive(Debug, Default)]
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(
+                Delay::new(Instant::now() + Duration::from_millis(100))
+                    .map(|_| FakeConnection)
+                    .map_err(|_| Error),
+            )
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| Pool::builder().max_size(5).build(Handler)))
+        .unwrap();
+
+    // Start a batch of checkouts, which open every connection and queue up,
+    // then give up on all of them.
+    let mut gets = (0..20).map(|_| pool.get()).collect::<Vec<_>>();
+    event_loop
+        .block_on(lazy(|| {
+            for get in &mut gets {
+                assert!(get.poll().unwrap().is_not_ready());
+            }
+            ok::<(), ()>(())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().waiters, 20);
+    assert_eq!(pool.state().pending_connections, 5);
+    mem::drop(gets);
+    assert_eq!(pool.state().waiters, 0);
+
+    // The connections opened for them go idle.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(300)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    let state = pool.state();
+    assert_eq!(state.pending_connections, 0);
+    assert_eq!(state.connections, 5);
+    assert_eq!(state.idle_connections, 5);
+
+    // A connection handed to a checkout that's dropped before seeing it goes
+    // back to the pool.
+    let mut conns = (0..5)
+        .map(|_| event_loop.block_on(pool.get()).unwrap())
+        .collect::<Vec<_>>();
+    let mut get = pool.get();
+    event_loop
+        .block_on(lazy(|| {
+            assert!(get.poll().unwrap().is_not_ready());
+            ok::<(), ()>(())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().waiters, 1);
+    conns.pop();
+    assert_eq!(pool.state().idle_connections, 0);
+    mem::drop(get);
+    let state = pool.state();
+    assert_eq!(state.waiters, 0);
+    assert_eq!(state.connections, 5);
+    assert_eq!(state.idle_connections, 1);
+}
+
+#[test]
+fn test_on_pool_drop() {
+    static CLOSED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Default)]
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(FakeConnection))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn on_pool_drop(&self, _: Self::Connection) {
+            CLOSED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder().max_size(3).min_idle(Some(3)).build(Handler)
+        }))
+        .unwrap();
+    let conn = event_loop.block_on(pool.get()).unwrap();
+
+    // Dropping the last handle doesn't close the checked out connection...
+    let pool2 = pool.clone();
+    mem::drop(pool);
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 0);
+    mem::drop(pool2);
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 0);
+
+    // ...but once it's returned, every idle connection is closed.
+    mem::drop(conn);
+    mem::drop(event_loop);
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_drain() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(2))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(pool.drain().then(move |r| {
+        tx.send(r.is_ok()).unwrap();
+        Ok(())
+    }));
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    // The idle connection has been closed, but the drain waits for the
+    // checked out one.
+    let state = pool.state();
+    assert_eq!(1, state.connections);
+    assert_eq!(0, state.idle_connections);
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::PoolClosed);
+
+    mem::drop(conn);
+    assert!(event_loop.block_on(rx).unwrap());
+    assert_eq!(0, pool.state().connections);
+}
+
+#[test]
+fn test_set_max_size() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .connection_timeout(Duration::from_secs(5))
+                .reaper_rate(Duration::from_millis(100))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+
+    // Growing the pool serves a checkout that was already waiting.
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(pool.get().then(move |r| {
+        tx.send(r.map(|_| ())).unwrap();
+        Ok(())
+    }));
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    event_loop
+        .block_on(lazy(|| {
+            pool.set_max_size(3);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert_eq!(event_loop.block_on(rx).unwrap(), Ok(()));
+    mem::drop(conn);
+    assert_eq!(pool.state().idle_connections, 3);
+
+    // Shrinking the pool closes excess idle connections at the next reaping.
+    event_loop
+        .block_on(lazy(|| {
+            pool.set_max_size(1);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(300)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 1);
+}
+
+#[test]
+fn test_shrink_returns() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(4)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conns = (0..4)
+        .map(|_| event_loop.block_on(pool.get()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(pool.state().connections, 4);
+
+    event_loop
+        .block_on(lazy(|| {
+            pool.set_max_size(2);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    let mut conns = conns.into_iter();
+    mem::drop(conns.next());
+    assert_eq!(pool.state().connections, 3);
+    assert_eq!(pool.state().idle_connections, 0);
+    mem::drop(conns.next());
+    assert_eq!(pool.state().connections, 2);
+    assert_eq!(pool.state().idle_connections, 0);
+
+    // Once the pool is back within its maximum, returned connections are
+    // kept again.
+    mem::drop(conns);
+    let state = pool.state();
+    assert_eq!(state.connections, 2);
+    assert_eq!(state.idle_connections, 2);
+}
+
+#[test]
+fn test_set_min_idle() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(3)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().connections, 0);
+
+    event_loop
+        .block_on(lazy(|| {
+            pool.set_min_idle(Some(2));
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(pool.state().idle_connections, 2);
+
+    // Checking out a connection opens another to stay at the new minimum.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(pool.state().connections, 3);
+    mem::drop(conn);
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| pool.set_min_idle(Some(4))));
+    assert!(result.is_err());
+    assert!(event_loop.block_on(pool.get()).is_ok());
+}
+
+#[test]
+fn test_wait_for_idle() {
+    use std::time::Instant;
+    use tokio::timer::Delay;
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(
+                Delay::new(Instant::now() + Duration::from_millis(20)).then(|_| Ok(FakeConnection)),
+            )
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            ok::<_, ()>(
+                Pool::builder()
+                    .max_size(5)
+                    .min_idle(Some(3))
+                    .build_unchecked(Handler),
+            )
+        }))
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 0);
+
+    event_loop.block_on(pool.wait_for_idle(3)).unwrap();
+    assert_eq!(pool.state().idle_connections, 3);
+
+    // Already satisfied, so this resolves straight away.
+    event_loop.block_on(pool.wait_for_idle(2)).unwrap();
+}
+
+#[test]
+fn test_clear() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(3)
+                .min_idle(Some(2))
+                .build(OkManager::<Connection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(pool.state().connections, 3);
+
+    event_loop.block_on(pool.clear()).unwrap();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 2);
+    let state = pool.state();
+    assert_eq!(state.connections, 3);
+    assert_eq!(state.idle_connections, 2);
+
+    // The connection checked out before clearing is not reused.
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conn);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 3);
+    let state = pool.state();
+    assert_eq!(state.connections, 2);
+    assert_eq!(state.idle_connections, 2);
+}
+
+#[test]
+fn test_pooled_connection_info() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(conn.uses(), 0);
+    mem::drop(conn);
+
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(conn.uses(), 1);
+    assert!(conn.age() >= Duration::from_millis(50));
+}
+
+#[test]
+fn test_connect_info() {
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct BackendPid(usize);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(FakeConnection))
+        }
+
+        fn connect_with_info(
+            &self,
+        ) -> Box<dyn Future<Item = (Self::Connection, ConnectInfo), Error = Self::Error> + Send>
+        {
+            let mut info = ConnectInfo::new();
+            info.insert(BackendPid(CONNECTED.fetch_add(1, Ordering::SeqCst)));
+            Box::new(ok((FakeConnection, info)))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| Pool::builder().max_size(2).build(Handler)))
+        .unwrap();
+
+    let conn1 = event_loop.block_on(pool.get()).unwrap();
+    let conn2 = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(conn1.connect_info().get(), Some(&BackendPid(0)));
+    assert_eq!(conn2.connect_info().get(), Some(&BackendPid(1)));
+    assert_eq!(conn1.connect_info().get::<u32>(), None);
+
+    // The information stays with the connection.
+    mem::drop(conn1);
+    let conn1 = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(conn1.connect_info().get(), Some(&BackendPid(0)));
+
+    // Managers that only implement `connect` record nothing.
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder().build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(conn.connect_info().get::<BackendPid>(), None);
+}
+
+#[test]
+fn test_metadata() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let mut conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(*conn.metadata::<u32>(), 0);
+    *conn.metadata::<u32>() += 1;
+    conn.metadata::<Vec<&str>>().push("prepared");
+    mem::drop(conn);
+
+    // The metadata is still there the next time the connection is checked out.
+    let mut conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(*conn.metadata::<u32>(), 1);
+    assert_eq!(*conn.metadata::<Vec<&str>>(), vec!["prepared"]);
+
+    // But not once it has been replaced.
+    conn.invalidate();
+    let mut conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(*conn.metadata::<u32>(), 0);
+}
+
+#[test]
+fn test_flush_broken() {
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+    static BROKEN: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Debug)]
+    struct Connection(usize);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection(CONNECTED.fetch_add(1, Ordering::SeqCst))))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            // The first two connections break.
+            if BROKEN.load(Ordering::SeqCst) && conn.0 < 2 {
+                Box::new(err((Error, conn)))
+            } else {
+                Box::new(ok(conn))
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder().max_size(3).min_idle(Some(3)).build(Handler)
+        }))
+        .unwrap();
+    assert_eq!(event_loop.block_on(pool.flush_broken()).unwrap(), 0);
+    assert_eq!(CONNECTED.load(Ordering::SeqCst), 3);
+
+    BROKEN.store(true, Ordering::SeqCst);
+    assert_eq!(event_loop.block_on(pool.flush_broken()).unwrap(), 2);
+    let state = pool.state();
+    assert_eq!(state.connections, 3);
+    assert_eq!(state.idle_connections, 3);
+    assert_eq!(CONNECTED.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn test_replace_broken() {
+    static BROKEN: AtomicBool = AtomicBool::new(false);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(FakeConnection))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            BROKEN.load(Ordering::SeqCst)
+        }
+    }
+
+    // Checks out a connection, returns it broken, and gives the pool a moment
+    // to replace it.
+    fn return_broken(builder: Builder<Handler>) -> State {
+        let mut event_loop = Runtime::new().unwrap();
+        let pool = event_loop
+            .block_on(lazy(|| builder.build(Handler)))
+            .unwrap();
+        BROKEN.store(false, Ordering::SeqCst);
+        let conn = event_loop.block_on(pool.get()).unwrap();
+        BROKEN.store(true, Ordering::SeqCst);
+        assert!(event_loop
+            .block_on(lazy(|| {
+                mem::drop(conn);
+                Timeout::new(empty::<(), ()>(), Duration::from_millis(50))
+            }))
+            .unwrap_err()
+            .is_elapsed());
+        pool.state()
+    }
+
+    // A broken connection is replaced straight away to maintain `min_idle`.
+    let state = return_broken(Pool::builder().max_size(2).min_idle(Some(2)));
+    assert_eq!(state.connections, 2);
+    assert_eq!(state.idle_connections, 2);
+
+    // Beyond `min_idle`, it's only replaced straight away with `replace_broken`.
+    let state = return_broken(Pool::builder().max_size(2).min_idle(Some(1)));
+    assert_eq!(state.connections, 1);
+
+    let state = return_broken(
+        Pool::builder()
+            .max_size(2)
+            .min_idle(Some(1))
+            .replace_broken(true),
+    );
+    assert_eq!(state.connections, 2);
+    assert_eq!(state.idle_connections, 2);
+}
+
+#[test]
+fn test_invalidate() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(1))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(pool.state().connections, 2);
+
+    event_loop
+        .block_on(lazy(|| {
+            conn.invalidate();
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 1);
+}
+
+#[test]
+fn test_max_waiters() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .max_waiters(Some(1))
+                .connection_timeout(Duration::from_secs(5))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(pool.get().then(move |r| {
+        tx.send(r.map(|_| ())).unwrap();
+        Ok(())
+    }));
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    // There's already one caller waiting, so this fails straight away.
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::QueueFull);
+
+    mem::drop(conn);
+    assert_eq!(event_loop.block_on(rx).unwrap(), Ok(()));
+}
+
+#[test]
+fn test_queue_strategy() {
+    use std::time::Instant;
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .queue_strategy(QueueStrategy::Fail)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    // The first checkout can open a connection, so it's allowed to wait.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::QueueFull);
+    mem::drop(conn);
+    assert!(event_loop.block_on(pool.get()).is_ok());
+
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_secs(5))
+                .queue_strategy(QueueStrategy::WaitBounded(Duration::from_millis(50)))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let _conn = event_loop.block_on(pool.get()).unwrap();
+    let start = Instant::now();
+    let e = event_loop.block_on(pool.get()).unwrap_err();
+    assert_eq!(e, RunError::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_keepalive() {
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+    static PINGED: AtomicUsize = AtomicUsize::new(0);
+    static INVALID: AtomicBool = AtomicBool::new(false);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            CONNECTED.fetch_add(1, Ordering::SeqCst);
+            Box::new(ok(FakeConnection))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            PINGED.fetch_add(1, Ordering::SeqCst);
+            if INVALID.swap(false, Ordering::SeqCst) {
+                Box::new(err((Error, conn)))
+            } else {
+                Box::new(ok(conn))
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .keepalive_interval(Some(Duration::from_millis(100)))
+                .reaper_rate(Duration::from_millis(100))
+                .build(Handler)
+        }))
+        .unwrap();
+
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(350)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert!(PINGED.load(Ordering::SeqCst) >= 2);
+    assert_eq!(CONNECTED.load(Ordering::SeqCst), 1);
+
+    // A connection that fails its ping gets replaced.
+    INVALID.store(true, Ordering::SeqCst);
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(250)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(CONNECTED.load(Ordering::SeqCst), 2);
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+}
+
+#[test]
+fn test_background_validation() {
+    static VALIDATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(FakeConnection))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            VALIDATED.fetch_add(1, Ordering::SeqCst);
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .background_validation(Some(Duration::from_secs(5)))
+                .reaper_rate(Duration::from_millis(50))
+                .build(Handler)
+        }))
+        .unwrap();
+
+    // A newly opened connection doesn't need validating.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 0);
+    mem::drop(conn);
+
+    // Once returned it's validated in the background, and then isn't validated
+    // again when it's checked out.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(150)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 1);
+    let _conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_detailed_state() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(2))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(100)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    // Use one of the connections so it is idle for less time than it's been open.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    mem::drop(conn);
+
+    let conns = pool.detailed_state();
+    assert_eq!(conns.len(), 2);
+    assert!(conns[0].age >= Duration::from_millis(100));
+    assert!(conns[0].idle_for >= Duration::from_millis(100));
+    assert!(conns[1].age >= Duration::from_millis(100));
+    assert!(conns[1].idle_for < Duration::from_millis(100));
+}
+
+#[test]
+fn test_executor() {
+    static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct CountingSpawn;
+
+    impl Spawn for CountingSpawn {
+        fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send>) {
+            SPAWNED.fetch_add(1, Ordering::SeqCst);
+            tokio::executor::spawn(future);
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(2))
+                .executor(Box::new(CountingSpawn))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    mem::drop(conn);
+    assert!(SPAWNED.load(Ordering::SeqCst) >= 2);
+    assert_eq!(pool.state().connections, 2);
+}
+
+#[test]
+fn test_executor_drops_futures() {
+    // Drops whatever it's given, as an executor that is shutting down might.
+    #[derive(Debug)]
+    struct DroppingSpawn;
+
+    impl Spawn for DroppingSpawn {
+        fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send>) {
+            mem::drop(future);
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(2))
+                .executor(Box::new(DroppingSpawn))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().connections, 0);
+    event_loop.block_on(lazy(|| pool.replenish())).unwrap();
+}
+
+#[test]
+fn test_disable_reaper() {
+    #[derive(Debug, Default)]
+    struct CountingSpawn(Arc<AtomicUsize>);
+
+    impl Spawn for CountingSpawn {
+        fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            tokio::executor::spawn(future);
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let mut spawned = |reaper| {
+        let count = Arc::new(AtomicUsize::new(0));
+        let executor = CountingSpawn(count.clone());
+        event_loop
+            .block_on(lazy(|| {
+                Pool::builder()
+                    .reaper(reaper)
+                    .executor(Box::new(executor))
+                    .build(OkManager::<FakeConnection>::new())
+            }))
+            .unwrap();
+        count.load(Ordering::SeqCst)
+    };
+
+    let enabled = spawned(true);
+    let disabled = spawned(false);
+    assert_eq!(enabled, disabled + 1);
+}
+
+#[test]
+fn test_on_return() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    static INVALID: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Debug)]
+    struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            if INVALID.load(Ordering::SeqCst) {
+                Box::new(err((Error, conn)))
+            } else {
+                Box::new(ok(conn))
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .test_on_check_out(false)
+                .test_on_return(true)
+                .build(Handler)
+        }))
+        .unwrap();
+
+    // A valid connection goes back into the pool.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conn);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // An invalid one is closed as soon as it's returned.
+    INVALID.store(true, Ordering::SeqCst);
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conn);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+    let state = pool.state();
+    assert_eq!(state.connections, 0);
+    assert_eq!(state.idle_connections, 0);
+}
+
+#[test]
+fn test_min_connections() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(3)
+                .min_connections(Some(2))
+                .idle_timeout(Some(Duration::from_millis(100)))
+                .reaper_rate(Duration::from_millis(100))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().connections, 2);
+
+    // Checked out connections count towards the minimum, so no more are
+    // opened than are needed to serve the checkouts.
+    let conns = event_loop
+        .block_on(join_all(vec![pool.get(), pool.get(), pool.get()]))
+        .unwrap();
+    let state = pool.state();
+    assert_eq!(state.connections, 3);
+    assert_eq!(state.idle_connections, 0);
+
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conns);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 3);
+
+    // Only the connection in excess of the minimum is reaped.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(350)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    let state = pool.state();
+    assert_eq!(state.connections, 2);
+    assert_eq!(state.idle_connections, 2);
+}
+
+#[test]
+fn test_observer() {
+    static ACQUIRED: AtomicUsize = AtomicUsize::new(0);
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+    static TIMED_OUT: AtomicUsize = AtomicUsize::new(0);
+    static REAPED: AtomicUsize = AtomicUsize::new(0);
+    static CONNECT_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Observer;
+
+    impl PoolObserver for Observer {
+        fn on_acquire(&self, _: Duration) {
+            ACQUIRED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_release(&self) {
+            RELEASED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_connect(&self) {
+            CONNECTED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_timeout(&self) {
+            TIMED_OUT.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_reap(&self, count: u32) {
+            REAPED.fetch_add(count as usize, Ordering::SeqCst);
+        }
+
+        fn on_connect_error(&self) {
+            CONNECT_ERRORS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_millis(100))
+                .idle_timeout(Some(Duration::from_millis(100)))
+                .reaper_rate(Duration::from_millis(100))
+                .observer(Box::new(Observer))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(CONNECTED.load(Ordering::SeqCst), 1);
+    assert_eq!(ACQUIRED.load(Ordering::SeqCst), 1);
+
+    assert!(event_loop.block_on(pool.get()).is_err());
+    assert_eq!(TIMED_OUT.load(Ordering::SeqCst), 1);
+
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conn);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(350)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(REAPED.load(Ordering::SeqCst), 1);
+    let state = pool.state();
+    assert_eq!(state.connections, 0);
+    assert_eq!(state.connections_reaped, 1);
+    assert_eq!(CONNECT_ERRORS.load(Ordering::SeqCst), 0);
+
+    let pool = event_loop
+        .block_on(lazy(|| {
+            ok::<_, ()>(
+                Pool::builder()
+                    .observer(Box::new(Observer))
+                    .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0)),
+            )
+        }))
+        .unwrap();
+    assert!(event_loop.block_on(pool.get()).is_err());
+    assert_eq!(CONNECT_ERRORS.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_max_uses() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .max_uses(Some(2))
+                .build(OkManager::<Connection>::new())
+        }))
+        .unwrap();
+
+    for _ in 0..3 {
+        let conn = event_loop.block_on(pool.get()).unwrap();
+        event_loop
+            .block_on(lazy(|| {
+                mem::drop(conn);
+                ok::<_, ()>(())
+            }))
+            .unwrap();
+    }
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+
+    // The worn out connection is replaced to maintain the idle count.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 1);
+}
+
+#[test]
+fn test_leak_detection() {
+    static LEAKED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Clone, Copy)]
+    struct LeakSink;
+
+    impl ErrorSink<Error> for LeakSink {
+        fn sink(&self, _: Error) {}
+
+        fn sink_leak(&self, error: LeakError) {
+            assert!(error.checked_out_for >= Duration::from_millis(100));
+            LEAKED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn boxed_clone(&self) -> Box<dyn ErrorSink<Error>> {
+            Box::new(*self)
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .leak_detection_threshold(Some(Duration::from_millis(100)))
+                .reaper_rate(Duration::from_millis(100))
+                .error_sink(Box::new(LeakSink))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    // A connection returned promptly isn't reported.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    mem::drop(conn);
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(350)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    // The held connection is reported once, and isn't reclaimed.
+    assert_eq!(LEAKED.load(Ordering::SeqCst), 1);
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 0);
+    mem::drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[test]
+fn test_lifo_order() {
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Connection(usize);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection(CONNECTED.fetch_add(1, Ordering::SeqCst))))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(2))
+                .connection_order(ConnectionOrder::Lifo)
+                .build(Handler)
+        }))
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 2);
+
+    // The most recently returned connection is reused every time.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    let first = conn.0;
+    mem::drop(conn);
+    for _ in 0..3 {
+        let conn = event_loop.block_on(pool.get()).unwrap();
+        assert_eq!(conn.0, first);
+    }
+    assert_eq!(CONNECTED.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_is_valid_mut() {
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Connection(usize);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection(CONNECTED.fetch_add(1, Ordering::SeqCst))))
+        }
+
+        fn is_valid_mut(
+            &self,
+            conn: &mut Self::Connection,
+        ) -> Box<dyn Future<Item = (), Error = Self::Error> + Send> {
+            if conn.0 == 0 {
+                Box::new(err(Error))
+            } else {
+                Box::new(ok(()))
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder().max_size(2).min_idle(Some(1)).build(Handler)
+        }))
+        .unwrap();
+
+    // The first connection fails validation and is never handed out.
+    for _ in 0..3 {
+        let conn = event_loop.block_on(pool.get()).unwrap();
+        assert_ne!(conn.0, 0);
+    }
+    assert!(pool.state().connections <= 2);
+}
+
+#[test]
+fn test_recycle() {
+    #[derive(Debug, Default)]
+    struct Connection {
+        dirty: bool,
+        poisoned: bool,
+    }
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection::default()))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn recycle(
+            &self,
+            mut conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            if conn.poisoned {
+                return Box::new(err((Error, conn)));
+            }
+            conn.dirty = false;
+            Box::new(ok(conn))
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| Pool::builder().max_size(1).build(Handler)))
+        .unwrap();
+
+    let mut conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(!conn.dirty);
+    conn.dirty = true;
+    mem::drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // The connection was reset on its way back.
+    let mut conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(!conn.dirty);
+    conn.poisoned = true;
+    mem::drop(conn);
+
+    // It couldn't be reset, so it was closed.
+    let state = pool.state();
+    assert_eq!(state.connections, 0);
+    assert_eq!(state.idle_connections, 0);
+}
+
+#[test]
+fn test_selection_policy() {
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Connection(usize);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection(CONNECTED.fetch_add(1, Ordering::SeqCst))))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    #[derive(Debug)]
+    struct LeastUsed;
+
+    impl SelectionPolicy<Connection> for LeastUsed {
+        fn select(&self, conns: &IdleConnections<Connection>) -> Option<usize> {
+            conns
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, conn)| (conn.uses(), conn.connection().0))
+                .map(|(i, _)| i)
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(3)
+                .min_idle(Some(3))
+                .selection_policy(Box::new(LeastUsed))
+                .build(Handler)
+        }))
+        .unwrap();
+
+    let mut used = Vec::new();
+    for _ in 0..6 {
+        let conn = event_loop.block_on(pool.get()).unwrap();
+        used.push(conn.0);
+    }
+    assert_eq!(used, vec![0, 1, 2, 0, 1, 2]);
+}
+
+#[test]
+fn test_retain() {
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Connection(usize);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(Connection(CONNECTED.fetch_add(1, Ordering::SeqCst))))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder().max_size(5).min_idle(Some(4)).build(Handler)
+        }))
+        .unwrap();
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(conn.0, 0);
+
+    // Only idle connections are considered, and those failing the predicate
+    // are replaced.
+    let closed = event_loop
+        .block_on(lazy(|| pool.retain(|conn| conn.connection().0 % 2 == 0)))
+        .unwrap();
+    assert_eq!(closed, 2);
+    assert_eq!(pool.state().idle_connections, 4);
+
+    let mut ids = Vec::new();
+    let closed = event_loop
+        .block_on(lazy(|| {
+            pool.retain(|conn| {
+                ids.push(conn.connection().0);
+                true
+            })
+        }))
+        .unwrap();
+    assert_eq!(closed, 0);
+    ids.sort();
+    assert_eq!(ids, vec![2, 4, 5, 6]);
+    mem::drop(conn);
+}
+
+#[test]
+fn test_fair_waiters() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let held = event_loop.block_on(pool.get()).unwrap();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let gets = (0..10)
+        .map(|i| {
+            let order = order.clone();
+            pool.get().map(move |conn| {
+                order.lock().unwrap().push(i);
+                mem::drop(conn);
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Every checkout starts waiting before the held connection is returned.
+    event_loop
+        .block_on(join_all(gets).join(lazy(move || {
+            mem::drop(held);
+            ok::<_, RunError<Error>>(())
+        })))
+        .unwrap();
+    assert_eq!(*order.lock().unwrap(), (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_priority_waiters() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let held = event_loop.block_on(pool.get()).unwrap();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let priorities = [
+        Priority::Low,
+        Priority::Normal,
+        Priority::High,
+        Priority::Low,
+        Priority::High,
+    ];
+    let runs = priorities
+        .iter()
+        .enumerate()
+        .map(|(i, &priority)| {
+            let order = order.clone();
+            pool.run_with_priority(priority, move |conn| {
+                order.lock().unwrap().push(i);
+                ok::<_, (Error, _)>(((), conn))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    event_loop
+        .block_on(join_all(runs).join(lazy(move || {
+            mem::drop(held);
+            ok::<_, RunError<Error>>(())
+        })))
+        .unwrap();
+    assert_eq!(*order.lock().unwrap(), vec![2, 4, 1, 0, 3]);
+}
+
+#[test]
+fn test_run_pooled() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let pool2 = pool.clone();
+    let idle = event_loop
+        .block_on(pool.run_pooled(move |conn| {
+            assert_eq!(pool2.state().idle_connections, 0);
+            conn.release();
+            // The rest of the work happens without the connection.
+            ok::<_, Error>(pool2.state().idle_connections)
+        }))
+        .unwrap();
+    assert_eq!(idle, 1);
+
+    let r: Result<(), _> = event_loop.block_on(pool.run_pooled(|_| err(Error)));
+    assert_eq!(r.unwrap_err(), RunError::User(Error));
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[test]
+fn test_run_ref() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<AtomicUsize>::new())
+        }))
+        .unwrap();
+
+    for i in 0..3 {
+        let pool2 = pool.clone();
+        let r = event_loop.block_on(pool.run_ref(move |conn: &AtomicUsize| {
+            assert_eq!(pool2.state().idle_connections, 0);
+            ok::<_, Error>(conn.fetch_add(1, Ordering::SeqCst))
+        }));
+        assert_eq!(r.unwrap(), i);
+        assert_eq!(pool.state().idle_connections, 1);
+    }
+
+    let r: Result<(), _> = event_loop.block_on(pool.run_ref(|_| err(Error)));
+    assert_eq!(r.unwrap_err(), RunError::User(Error));
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[test]
+fn test_run_with_disposition() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<AtomicUsize>::new())
+        }))
+        .unwrap();
+
+    let mut uses = |disposition, fail| {
+        event_loop.block_on(pool.run_with_disposition(move |conn: AtomicUsize| {
+            let uses = conn.fetch_add(1, Ordering::SeqCst);
+            if fail {
+                Err((Error, conn, disposition))
+            } else {
+                Ok((uses, conn, disposition))
+            }
+        }))
+    };
+
+    assert_eq!(uses(Disposition::Keep, false), Ok(0));
+    assert_eq!(uses(Disposition::Keep, false), Ok(1));
+    // A discarded connection is replaced by a fresh one.
+    assert_eq!(uses(Disposition::Discard, false), Ok(2));
+    assert_eq!(uses(Disposition::Keep, false), Ok(0));
+    // Failing doesn't discard the connection unless the closure says so.
+    assert_eq!(uses(Disposition::Keep, true), Err(RunError::User(Error)));
+    assert_eq!(uses(Disposition::Discard, true), Err(RunError::User(Error)));
+    assert_eq!(uses(Disposition::Keep, false), Ok(0));
+}
+
+#[test]
+fn test_healthcheck() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert!(event_loop.block_on(pool.healthcheck()).unwrap());
+
+    // With every connection checked out, a dedicated connection is tried.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(event_loop.block_on(pool.healthcheck()).unwrap());
+    mem::drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    let pool = event_loop
+        .block_on(lazy(|| {
+            ok::<_, ()>(
+                Pool::builder()
+                    .healthcheck_timeout(Duration::from_millis(100))
+                    .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0)),
+            )
+        }))
+        .unwrap();
+    assert!(!event_loop.block_on(pool.healthcheck()).unwrap());
+}
+
+#[test]
+fn test_park() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .max_lifetime(Some(Duration::from_millis(200)))
+                .reaper_rate(Duration::from_millis(50))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let mut conn = event_loop.block_on(pool.get()).unwrap();
+    *conn.metadata::<u32>() = 7;
+    let token = conn.park();
+
+    // The parked connection isn't handed to anyone else.
+    assert!(event_loop
+        .block_on(pool.get_timeout(Duration::from_millis(50)))
+        .is_err());
+
+    let mut conn = pool.unpark(token).unwrap();
+    assert_eq!(*conn.metadata::<u32>(), 7);
+    let token = conn.park();
+
+    // But it's still reaped once it exceeds its lifetime.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(400)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert!(pool.unpark(token).is_none());
+    assert_eq!(pool.state().connections, 0);
+
+    // Dropping a token closes its connection rather than leaking its slot.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    let token = conn.park();
+    assert_eq!(pool.state().connections, 1);
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(token);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().connections, 0);
+    assert!(event_loop.block_on(pool.get()).is_ok());
+
+    // A token can't reclaim a connection from another pool.
+    let other = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    let token = event_loop.block_on(other.get()).unwrap().park();
+    assert!(pool.unpark(token).is_none());
+    assert_eq!(other.state().connections, 0);
+}
+
+#[test]
+fn test_reservation() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    let reserved = pool.with_reservation(1);
+    let short = Duration::from_millis(100);
+
+    // The second slot is kept for the reservation.
+    let held = event_loop.block_on(pool.get()).unwrap();
+    assert!(event_loop.block_on(pool.get_timeout(short)).is_err());
+
+    let reserved_conn = event_loop.block_on(reserved.get_timeout(short)).unwrap();
+    // Checkouts beyond the reservation share the rest of the pool.
+    assert!(event_loop.block_on(reserved.get_timeout(short)).is_err());
+
+    // A returned connection is kept for the reservation rather than handed to a
+    // waiting checkout without one.
+    let waiting = pool.get_timeout(Duration::from_millis(300));
+    mem::drop(reserved_conn);
+    assert!(event_loop.block_on(waiting).is_err());
+
+    // The kept connection serves the reservation.
+    let reserved_conn = event_loop.block_on(reserved.get_timeout(short)).unwrap();
+    mem::drop(reserved_conn);
+    mem::drop(held);
+
+    // Dropping the reservation frees its slot.
+    mem::drop(reserved);
+    let _conn1 = event_loop.block_on(pool.get_timeout(short)).unwrap();
+    let _conn2 = event_loop.block_on(pool.get_timeout(short)).unwrap();
+}
+
+#[test]
+fn test_available_permits() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(4)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert_eq!(pool.available_permits(), 4);
+
+    let conn1 = event_loop.block_on(pool.get()).unwrap();
+    let conn2 = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(pool.available_permits(), 2);
+
+    // Idle connections count alongside the ones the pool could still open.
+    mem::drop(conn1);
+    assert_eq!(pool.available_permits(), 3);
+
+    // Unused reserved slots are only available to the reservation.
+    let reserved = pool.with_reservation(1);
+    assert_eq!(pool.available_permits(), 2);
+    assert_eq!(reserved.available_permits(), 3);
+
+    let reserved_conn = event_loop.block_on(reserved.get()).unwrap();
+    assert_eq!(pool.available_permits(), 2);
+    assert_eq!(reserved.available_permits(), 2);
+
+    mem::drop(reserved_conn);
+    mem::drop(conn2);
+    mem::drop(reserved);
+    assert_eq!(pool.available_permits(), 4);
+}
+
+#[test]
+fn test_name() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .name("primary")
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert_eq!(pool.name(), Some("primary"));
+    assert!(format!("{:?}", pool).starts_with("Pool(\"primary\", "));
+
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder().build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    assert_eq!(pool.name(), None);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing() {
+    use std::collections::HashMap;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    type Spans = Arc<
+        Mutex<
+            Vec<(
+                &'static str,
+                HashMap<&'static str, String>,
+                &'static Metadata<'static>,
+            )>,
+        >,
+    >;
+
+    struct Fields<'a>(&'a mut HashMap<&'static str, String>);
+
+    impl<'a> Visit for Fields<'a> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name(), value.to_owned());
+        }
+    }
+
+    // Records the name and fields of every span, and tracks the entered one.
+    struct Recorder(Spans, Mutex<Vec<(Id, &'static Metadata<'static>)>>);
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes) -> Id {
+            let mut spans = self.0.lock().unwrap();
+            let mut fields = HashMap::new();
+            span.record(&mut Fields(&mut fields));
+            spans.push((span.metadata().name(), fields, span.metadata()));
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record) {
+            let mut spans = self.0.lock().unwrap();
+            let fields = &mut spans[span.into_u64() as usize - 1].1;
+            values.record(&mut Fields(fields));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event) {}
+
+        fn enter(&self, span: &Id) {
+            let metadata = self.0.lock().unwrap()[span.into_u64() as usize - 1].2;
+            self.1.lock().unwrap().push((span.clone(), metadata));
+        }
+
+        fn exit(&self, _: &Id) {
+            self.1.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> Current {
+            match self.1.lock().unwrap().last() {
+                Some(&(ref span, metadata)) => Current::new(span.clone(), metadata),
+                None => Current::none(),
+            }
+        }
+    }
+
+    let spans = Spans::default();
+    let recorder = Recorder(spans.clone(), Mutex::default());
+    tracing::subscriber::with_default(recorder, || {
+        let mut event_loop = Runtime::new().unwrap();
+        let pool = event_loop
+            .block_on(lazy(|| {
+                Pool::builder()
+                    .name("primary")
+                    .max_size(1)
+                    .build(OkManager::<FakeConnection>::new())
+            }))
+            .unwrap();
+
+        // The first checkout opens a connection, the second reuses it and the
+        // third times out waiting for it to be returned.
+        let conn = event_loop.block_on(pool.get()).unwrap();
+        mem::drop(conn);
+        let conn = event_loop.block_on(pool.get()).unwrap();
+        let e = event_loop
+            .block_on(pool.get_timeout(Duration::from_millis(10)))
+            .unwrap_err();
+        assert!(e.is_timeout());
+        mem::drop(conn);
+    });
+
+    let spans = spans.lock().unwrap();
+    let summary = spans
+        .iter()
+        .map(|(name, fields, _)| {
+            assert_eq!(fields["pool"], "primary");
+            assert!(fields.contains_key("elapsed_ms"));
+            let path = fields.get("path").map(String::as_str);
+            (*name, path, fields["outcome"].as_str())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            ("bb8.get", Some("opened"), "ok"),
+            ("bb8.connect", None, "ok"),
+            ("bb8.get", Some("idle"), "ok"),
+            ("bb8.get", Some("queued"), "timed_out"),
+        ]
+    );
+}
+
+#[test]
+fn test_timed_out_waiter_removed() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .max_waiters(Some(1))
+                .connection_timeout(Duration::from_millis(100))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let held = event_loop.block_on(pool.get()).unwrap();
+    match event_loop.block_on(pool.get()) {
+        Err(RunError::TimedOut) => {}
+        _ => panic!("expected the checkout to time out"),
+    }
+
+    // The timed out checkout no longer occupies the only slot in the queue.
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(pool.get().then(move |r| {
+        tx.send(r.is_ok()).unwrap();
+        Ok(())
+    }));
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(held);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert!(event_loop.block_on(rx).unwrap());
+}
+
+#[test]
+fn test_many_canceled_waiters() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+    let held = event_loop.block_on(pool.get()).unwrap();
+
+    // Queue checkouts that will give up around one that keeps waiting.
+    let queue = |n| {
+        let pool = pool.clone();
+        lazy(move || {
+            let mut checkouts = Vec::new();
+            for _ in 0..n {
+                let mut checkout = pool.get();
+                assert!(checkout.poll().unwrap().is_not_ready());
+                checkouts.push(checkout);
+            }
+            ok::<_, ()>(checkouts)
+        })
+    };
+    let before = event_loop.block_on(queue(1000)).unwrap();
+    let waiting = event_loop.block_on(queue(1)).unwrap().pop().unwrap();
+    let after = event_loop.block_on(queue(1000)).unwrap();
+    assert_eq!(pool.state().waiters, 2001);
+
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(after);
+            mem::drop(before);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().waiters, 1);
+
+    // The returned connection goes to the checkout still waiting.
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(held);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert!(event_loop.block_on(waiting).is_ok());
+    assert_eq!(pool.state().waiters, 0);
+}
+
+#[cfg(feature = "std-future")]
+#[test]
+fn test_std_future() {
+    use futures03::future::{ready, FutureExt, TryFutureExt};
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop
+        .block_on(pool.get_async().boxed().compat())
+        .unwrap();
+    mem::drop(conn);
+
+    let value = event_loop
+        .block_on(
+            pool.run_async(|conn| ready(Ok::<_, (Error, _)>((42, conn))))
+                .boxed()
+                .compat(),
+        )
+        .unwrap();
+    assert_eq!(value, 42);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_testing_managers() {
+    use bb8::testing::{BrokenManager, OkManager, TestError};
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .connection_retry(2, Duration::from_millis(1), Duration::from_millis(1))
+                .build(OkManager::new().connect_delay(Duration::from_millis(10)))
+        }))
+        .unwrap();
+
+    // Failed attempts are retried.
+    pool.manager().fail_next_connects(2);
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(conn.id(), 0);
+    assert_eq!(pool.manager().connect_attempts(), 3);
+    assert_eq!(pool.manager().connections_created(), 1);
+
+    // A broken connection is closed as it's returned.
+    pool.manager().set_broken(true);
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conn);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().connections, 0);
+    pool.manager().set_broken(false);
+
+    // An invalid connection is replaced on checkout.
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    mem::drop(conn);
+    pool.manager().set_invalid(true);
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(conn.id(), 2);
+    mem::drop(conn);
+    pool.manager().set_invalid(false);
+
+    pool.manager().set_connect_fails(true);
+    let pool = pool.with_connection_timeout(Duration::from_millis(100));
+    event_loop.block_on(pool.clear()).unwrap();
+    match event_loop.block_on(pool.get()) {
+        Err(RunError::User(TestError)) => {}
+        _ => panic!("expected opening a connection to fail"),
+    }
+    assert_eq!(pool.manager().connect_attempts(), 8);
+
+    let manager = BrokenManager::new();
+    let e = event_loop.block_on(lazy(|| Pool::builder().min_idle(Some(1)).build(manager)));
+    assert_eq!(e.unwrap_err(), TestError);
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_get_blocking() {
+    use tokio::runtime::{Runtime, TaskExecutor};
+
+    #[derive(Debug)]
+    struct RuntimeSpawn(TaskExecutor);
+
+    impl Spawn for RuntimeSpawn {
+        fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send>) {
+            self.0.spawn(future);
+        }
+    }
+
+    let mut runtime = Runtime::new().unwrap();
+    let executor = runtime.executor();
+    let pool = runtime
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .executor(Box::new(RuntimeSpawn(executor)))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    // This thread isn't one of the runtime's.
+    let conn = pool.get_blocking().unwrap();
+    assert_eq!(pool.state().idle_connections, 0);
+    mem::drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+    let _conn = pool.get_blocking().unwrap();
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_get_blocking_default_executor() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    // No runtime is running on this thread.
+    let conn = pool.get_blocking().unwrap();
+    assert_eq!(pool.state().connections, 1);
+    assert_eq!(pool.state().pending_connections, 0);
+    mem::drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // A checkout that has to wait times out as usual.
+    let _conn = pool.get_blocking().unwrap();
+    let pool = pool.with_connection_timeout(Duration::from_millis(50));
+    assert!(pool.get_blocking().unwrap_err().is_timeout());
+}
+
+#[test]
+fn test_state_during_churn() {
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let pool = runtime
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(4)
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    // Spawn everything separately so the checkouts and reads run in parallel.
+    let executor = runtime.executor();
+    let checkouts = (0..8)
+        .map(|_| {
+            let pool = pool.clone();
+            let f = loop_fn(0, move |i| {
+                pool.get().map(move |conn| {
+                    mem::drop(conn);
+                    if i == 100 {
+                        Loop::Break(())
+                    } else {
+                        Loop::Continue(i + 1)
+                    }
+                })
+            });
+            oneshot::spawn(f, &executor)
+        })
+        .collect::<Vec<_>>();
+    let readers = (0..4)
+        .map(|_| {
+            let pool = pool.clone();
+            let f = loop_fn(0, move |i| {
+                let state = pool.state();
+                assert!(state.connections <= 4);
+                assert!(state.idle_connections <= 4);
+                if i == 1000 {
+                    ok::<_, RunError<Error>>(Loop::Break(()))
+                } else {
+                    ok(Loop::Continue(i + 1))
+                }
+            });
+            oneshot::spawn(f, &executor)
+        })
+        .collect::<Vec<_>>();
+    runtime
+        .block_on(join_all(checkouts).join(join_all(readers)))
+        .unwrap();
+
+    let state = pool.state();
+    assert!(state.connections >= 1 && state.connections <= 4);
+    assert_eq!(state.idle_connections, state.connections);
+}
+
+#[test]
+fn test_create_timeout() {
+    static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+    static TIMED_OUT: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Clone, Copy)]
+    struct TimeoutSink;
+
+    impl ErrorSink<Error> for TimeoutSink {
+        fn sink(&self, _: Error) {}
+
+        fn sink_create_timeout(&self, error: CreateTimeoutError) {
+            assert_eq!(error.timeout, Duration::from_millis(100));
+            TIMED_OUT.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn boxed_clone(&self) -> Box<dyn ErrorSink<Error>> {
+            Box::new(*self)
+        }
+    }
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            // The first attempt hangs forever.
+            if ATTEMPTS.fetch_add(1, Ordering::SeqCst) == 0 {
+                Box::new(empty())
+            } else {
+                Box::new(ok(FakeConnection))
+            }
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_millis(300))
+                .create_timeout(Some(Duration::from_millis(100)))
+                .error_sink(Box::new(TimeoutSink))
+                .build(Handler)
+        }))
+        .unwrap();
+
+    match event_loop.block_on(pool.get()) {
+        Err(RunError::TimedOut) => {}
+        _ => panic!("expected the checkout to time out"),
+    }
+    assert_eq!(TIMED_OUT.load(Ordering::SeqCst), 1);
+
+    // The hung attempt gave up its slot, so another connection can be opened.
+    assert!(event_loop.block_on(pool.get()).is_ok());
+    assert_eq!(ATTEMPTS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_round_robin() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(4)
+                .min_idle(Some(4))
+                .build(RoundRobin::new(vec![
+                    OkManager::<FakeConnection>::new(),
+                    OkManager::<FakeConnection>::new(),
+                ]))
+        }))
+        .unwrap();
+    assert_eq!(pool.manager().connections_per_backend(), vec![2, 2]);
+
+    let conns = event_loop
+        .block_on(join_all(vec![pool.get(), pool.get(), pool.get()]))
+        .unwrap();
+    let mut backends = conns.iter().map(|conn| conn.backend()).collect::<Vec<_>>();
+    backends.sort();
+    assert!(backends == vec![0, 0, 1] || backends == vec![0, 1, 1]);
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conns);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+
+    // Closing connections is reflected in the per backend counts.
+    event_loop.block_on(pool.shutdown()).unwrap();
+    assert_eq!(pool.manager().connections_per_backend(), vec![0, 0]);
+}
+
+#[test]
+fn test_keyed() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder().max_size(2).build(Keyed::new(vec![
+                ("a", OkManager::<FakeConnection>::new()),
+                ("b", OkManager::<FakeConnection>::new()),
+                ("c", OkManager::<FakeConnection>::new()),
+            ]))
+        }))
+        .unwrap();
+    let release = |event_loop: &mut Runtime, conns: Vec<PooledConnection<_>>| {
+        event_loop
+            .block_on(lazy(|| {
+                mem::drop(conns);
+                ok::<_, ()>(())
+            }))
+            .unwrap();
+    };
+    let key_state = |pool: &Pool<_>, key| {
+        let state = pool.keyed_state()[key];
+        (state.connections, state.idle_connections)
+    };
+
+    // Connections are opened to the requested backend, and reused.
+    let a = event_loop.block_on(pool.get_keyed("a")).unwrap();
+    assert_eq!(*a.key(), "a");
+    release(&mut event_loop, vec![a]);
+    assert_eq!(key_state(&pool, "a"), (1, 1));
+    let a = event_loop.block_on(pool.get_keyed("a")).unwrap();
+    let b = event_loop.block_on(pool.get_keyed("b")).unwrap();
+    assert_eq!(*b.key(), "b");
+    assert_eq!(pool.state().connections_created, 2);
+    release(&mut event_loop, vec![a, b]);
+
+    // With the pool full, the longest idle connection to another backend is
+    // closed to make room.
+    let c = event_loop.block_on(pool.get_keyed("c")).unwrap();
+    assert_eq!(*c.key(), "c");
+    assert_eq!(key_state(&pool, "a"), (0, 0));
+    assert_eq!(key_state(&pool, "b"), (1, 1));
+    assert_eq!(key_state(&pool, "c"), (1, 0));
+
+    // With every connection checked out, the next one returned is swapped for
+    // a connection to the requested backend.
+    let b = event_loop.block_on(pool.get_keyed("b")).unwrap();
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(pool.get_keyed("a").then(move |r| {
+        tx.send(r.map(|conn| *conn.key())).unwrap();
+        Ok(())
+    }));
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(10)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    release(&mut event_loop, vec![b]);
+    assert_eq!(event_loop.block_on(rx).unwrap(), Ok("a"));
+    assert_eq!(key_state(&pool, "a"), (1, 1));
+    assert_eq!(key_state(&pool, "b"), (0, 0));
+    assert_eq!(pool.state().connections, 2);
+    release(&mut event_loop, vec![c]);
+}
+
+#[test]
+fn test_failover() {
+    static PRIMARY_DOWN: AtomicBool = AtomicBool::new(true);
+    static FALLBACK_DOWN: AtomicBool = AtomicBool::new(false);
+    static PROBES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct ProbeSpawn;
+
+    impl Spawn for ProbeSpawn {
+        fn spawn(&self, future: Box<dyn Future<Item = (), Error = ()> + Send>) {
+            PROBES.fetch_add(1, Ordering::SeqCst);
+            tokio::executor::spawn(future);
+        }
+    }
+
+    struct Handler {
+        down: &'static AtomicBool,
+    }
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            if self.down.load(Ordering::SeqCst) {
+                Box::new(err(Error))
+            } else {
+                Box::new(ok(FakeConnection))
+            }
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            let manager = Failover::new(
+                Handler {
+                    down: &PRIMARY_DOWN,
+                },
+                Handler {
+                    down: &FALLBACK_DOWN,
+                },
+            )
+            .failure_threshold(2)
+            .probe_interval(Duration::from_millis(100))
+            .executor(Box::new(ProbeSpawn));
+            Pool::builder().max_size(2).build(manager)
+        }))
+        .unwrap();
+
+    // Connections come from the fallback while the primary is failing, and
+    // it's considered down after two failures.
+    let conn1 = event_loop.block_on(pool.get()).unwrap();
+    assert!(conn1.is_fallback());
+    assert!(!pool.manager().is_failed_over());
+    let conn2 = event_loop.block_on(pool.get()).unwrap();
+    assert!(conn2.is_fallback());
+    assert!(pool.manager().is_failed_over());
+
+    // The primary recovers, but isn't probed again until the interval passes.
+    PRIMARY_DOWN.store(false, Ordering::SeqCst);
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conn1);
+            mem::drop(conn2);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert!(pool.manager().is_failed_over());
+    assert_eq!(pool.state().idle_connections, 2);
+
+    // Checking out a fallback connection probes the primary in the background.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(150)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert!(conn.is_fallback());
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert!(!pool.manager().is_failed_over());
+    assert_eq!(PROBES.load(Ordering::SeqCst), 1);
+
+    // Fallback connections are closed as they're returned, and new ones come
+    // from the primary.
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conn);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    assert_eq!(pool.state().connections, 1);
+    let conn1 = event_loop.block_on(pool.get()).unwrap();
+    let conn2 = event_loop.block_on(pool.get()).unwrap();
+    assert!(conn1.is_fallback());
+    assert!(!conn2.is_fallback());
+}
+
+#[cfg(feature = "histogram")]
+#[test]
+fn test_latency_histogram() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(
+        pool.get()
+            .map(move |conn| {
+                mem::drop(conn);
+                tx.send(()).unwrap();
+            })
+            .map_err(|_| ()),
+    );
+
+    // Let the second checkout wait for a bit.
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(100)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+
+    mem::drop(conn);
+    event_loop.block_on(rx).unwrap();
+
+    // Only the checkout that waited is counted, in a bucket above 100ms.
+    let histogram = pool.latency_histogram();
+    assert_eq!(histogram.iter().map(|&(_, count)| count).sum::<u64>(), 1);
+    let &(bound, _) = histogram.iter().find(|&&(_, count)| count == 1).unwrap();
+    assert!(bound > Duration::from_millis(100));
+    assert_eq!(histogram[LATENCY_BUCKETS - 1].0, Duration::MAX);
+}
+
+#[test]
+fn test_dedicated_connection_timeout() {
+    static HANG: AtomicBool = AtomicBool::new(false);
+    static VALIDATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            if HANG.load(Ordering::SeqCst) {
+                Box::new(empty())
+            } else {
+                Box::new(ok(FakeConnection))
+            }
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            VALIDATED.fetch_add(1, Ordering::SeqCst);
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .connection_timeout(Duration::from_millis(100))
+                .build(Handler)
+        }))
+        .unwrap();
+
+    event_loop.block_on(pool.dedicated_connection()).unwrap();
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 0);
+    event_loop
+        .block_on(pool.get_dedicated_with_timeout(Duration::from_millis(100), true))
+        .unwrap();
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 1);
+
+    HANG.store(true, Ordering::SeqCst);
+    match event_loop.block_on(pool.dedicated_connection()) {
+        Err(RunError::TimedOut) => {}
+        _ => panic!("expected the connection attempt to time out"),
+    }
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[test]
+fn test_run_panic() {
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .connection_timeout(Duration::from_millis(100))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    let result =
+        panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            event_loop.block_on(pool.run(
+                |_| -> Result<((), FakeConnection), (Error, FakeConnection)> { panic!("boom") },
+            ))
+        }));
+    assert!(result.is_err());
+    assert_eq!(pool.state().connections, 0);
+
+    // The lost connection's slot is available again.
+    let mut event_loop = Runtime::new().unwrap();
+    assert!(event_loop.block_on(pool.get()).is_ok());
+}
+
+#[test]
+fn test_build_without_minimum() {
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            CONNECTED.fetch_add(1, Ordering::SeqCst);
+            Box::new(ok(FakeConnection))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| Pool::builder().build(Handler)))
+        .unwrap();
+    assert_eq!(pool.state().connections, 0);
+
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(CONNECTED.load(Ordering::SeqCst), 0);
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[test]
+fn test_warmup_concurrency() {
+    use std::time::Instant;
+    use tokio::timer::Delay;
+
+    static CONNECTING: AtomicUsize = AtomicUsize::new(0);
+    static MAX_CONNECTING: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            let connecting = CONNECTING.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_CONNECTING.fetch_max(connecting, Ordering::SeqCst);
+            Box::new(
+                Delay::new(Instant::now() + Duration::from_millis(10)).then(|_| {
+                    CONNECTING.fetch_sub(1, Ordering::SeqCst);
+                    Ok(FakeConnection)
+                }),
+            )
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(10)
+                .min_idle(Some(10))
+                .warmup_concurrency(2)
+                .build(Handler)
+        }))
+        .unwrap();
+
+    assert_eq!(pool.state().idle_connections, 10);
+    assert_eq!(MAX_CONNECTING.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_poll_broken() {
+    use std::time::Instant;
+    use tokio::timer::Delay;
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            Box::new(ok(FakeConnection))
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            Box::new(ok(conn))
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn poll_broken(
+            &self,
+            _: &mut Self::Connection,
+        ) -> Box<dyn Future<Item = bool, Error = ()> + Send> {
+            Box::new(Delay::new(Instant::now() + Duration::from_millis(10)).then(|_| Ok(true)))
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| Pool::builder().max_size(1).build(Handler)))
+        .unwrap();
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    event_loop
+        .block_on(lazy(|| {
+            mem::drop(conn);
+            ok::<_, ()>(())
+        }))
+        .unwrap();
+    // The connection is still counted while the check is in flight.
+    assert_eq!(pool.state().connections, 1);
+    assert_eq!(pool.state().idle_connections, 0);
+
+    assert!(event_loop
+        .block_on(lazy(|| Timeout::new(
+            empty::<(), ()>(),
+            Duration::from_millis(50)
+        )))
+        .unwrap_err()
+        .is_elapsed());
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[test]
+fn test_on_close() {
+    static CLOSED: AtomicUsize = AtomicUsize::new(0);
+    static FAIL: AtomicBool = AtomicBool::new(true);
+    static CONTEXTS: Mutex<Vec<ErrorContext>> = Mutex::new(Vec::new());
+
+    #[derive(Debug)]
+    struct Customizer;
+
+    impl ConnectionCustomizer<FakeConnection, Error> for Customizer {
+        fn on_close(
+            &self,
+            _: FakeConnection,
+        ) -> Option<Box<dyn Future<Item = (), Error = Error> + Send>> {
+            Some(Box::new(lazy(|| {
+                CLOSED.fetch_add(1, Ordering::SeqCst);
+                if FAIL.swap(false, Ordering::SeqCst) {
+                    Err(Error)
+                } else {
+                    Ok(())
+                }
+            })))
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct ContextSink;
+
+    impl ErrorSink<Error> for ContextSink {
+        fn sink(&self, _: Error) {
+            panic!("errors should be sunk with their context");
+        }
+
+        fn sink_with_context(&self, _: Error, context: ErrorContext) {
+            CONTEXTS.lock().unwrap().push(context);
+        }
+
+        fn boxed_clone(&self) -> Box<dyn ErrorSink<Error>> {
+            Box::new(*self)
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(2))
+                .connection_customizer(Box::new(Customizer))
+                .error_sink(Box::new(ContextSink))
+                .build(OkManager::<FakeConnection>::new())
+        }))
+        .unwrap();
+
+    // Shutting down waits for both teardowns, even though the first fails.
+    event_loop.block_on(lazy(|| pool.shutdown())).unwrap();
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 2);
+    assert_eq!(*CONTEXTS.lock().unwrap(), vec![ErrorContext::Close]);
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[test]
+fn test_error_context() {
+    static FAIL_CONNECT: AtomicBool = AtomicBool::new(true);
+    static INVALID: AtomicBool = AtomicBool::new(false);
+    static CONTEXTS: Mutex<Vec<ErrorContext>> = Mutex::new(Vec::new());
+
+    #[derive(Debug, Clone, Copy)]
+    struct ContextSink;
+
+    impl ErrorSink<Error> for ContextSink {
+        fn sink(&self, _: Error) {
+            panic!("errors should be sunk with their context");
+        }
+
+        fn sink_with_context(&self, _: Error, context: ErrorContext) {
+            CONTEXTS.lock().unwrap().push(context);
+        }
+
+        fn boxed_clone(&self) -> Box<dyn ErrorSink<Error>> {
+            Box::new(*self)
+        }
+    }
+
+    struct Handler;
+
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        fn connect(&self) -> Box<dyn Future<Item = Self::Connection, Error = Self::Error> + Send> {
+            if FAIL_CONNECT.swap(false, Ordering::SeqCst) {
+                Box::new(err(Error))
+            } else {
+                Box::new(ok(FakeConnection))
+            }
+        }
+
+        fn is_valid(
+            &self,
+            conn: Self::Connection,
+        ) -> Box<dyn Future<Item = Self::Connection, Error = (Self::Error, Self::Connection)> + Send>
+        {
+            if INVALID.swap(false, Ordering::SeqCst) {
+                Box::new(err((Error, conn)))
+            } else {
+                Box::new(ok(conn))
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = event_loop
+        .block_on(lazy(|| {
+            Pool::builder()
+                .max_size(1)
+                .min_idle(Some(1))
+                .connection_retry(1, Duration::from_millis(10), Duration::from_millis(10))
+                .error_sink(Box::new(ContextSink))
+                .build(Handler)
+        }))
+        .unwrap();
+    assert_eq!(*CONTEXTS.lock().unwrap(), vec![ErrorContext::Connect]);
+
+    // The idle connection fails validation, and is replaced to satisfy the checkout.
+    INVALID.store(true, Ordering::SeqCst);
+    event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(
+        *CONTEXTS.lock().unwrap(),
+        vec![ErrorContext::Connect, ErrorContext::CheckOut]
+    );
+}
+
+#[test]
+fn test_run_error_helpers() {
+    let user = RunError::User(Error);
+    assert!(!user.is_timeout());
+    assert!(!user.is_pool_error());
+    assert_eq!(user.as_user(), Some(&Error));
+    assert_eq!(user.into_user(), Some(Error));
+
+    assert_eq!(RunError::from(Error), RunError::User(Error));
+    assert_eq!(
+        RunError::User(Error).map_user(|e| e.to_string()),
+        RunError::User("blammo".to_string())
+    );
+    assert_eq!(
+        RunError::<Error>::TimedOut.map_user(|e| e.to_string()),
+        RunError::TimedOut
+    );
+    let boxed: RunError<Box<dyn error::Error>> = RunError::User(Error).err_into();
+    assert_eq!(boxed.into_user().unwrap().to_string(), "blammo");
+
+    let boxed = RunError::User(Error).into_boxed_error();
+    assert_eq!(boxed.to_string(), "blammo");
+    assert_eq!(
+        boxed.downcast::<RunError<Error>>().unwrap(),
+        Box::new(RunError::User(Error))
+    );
+    let boxed = RunError::<Error>::TimedOut.into_boxed_error();
+    assert_eq!(boxed.to_string(), "Timed out in bb8");
+
+    for e in [
+        RunError::TimedOut,
+        RunError::CircuitOpen,
+        RunError::PoolClosed,
+        RunError::QueueFull,
+    ] {
+        assert_eq!(e.is_timeout(), e == RunError::TimedOut);
+        assert!(e.is_pool_error());
+        assert_eq!(e.as_user(), None);
+        assert_eq!(e.into_user(), None::<Error>);
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_metrics() {
+    use metrics::{
+        Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString,
+        Unit,
+    };
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicU64;
+
+    #[derive(Default)]
+    struct Samples(Mutex<Vec<f64>>);
+
+    impl HistogramFn for Samples {
+        fn record(&self, value: f64) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    // Keeps the values of the metrics of the pool named "primary".
+    #[derive(Default)]
+    struct TestRecorder {
+        values: Mutex<HashMap<String, Arc<AtomicU64>>>,
+        samples: Arc<Samples>,
+    }
+
+    impl TestRecorder {
+        fn value(&self, key: &Key) -> Arc<AtomicU64> {
+            assert_eq!(
+                key.labels()
+                    .map(|l| (l.key(), l.value()))
+                    .collect::<Vec<_>>(),
+                vec![("pool", "primary")]
+            );
+            let mut values = self.values.lock().unwrap();
+            values.entry(key.name().to_owned()).or_default().clone()
+        }
+
+        fn get(&self, name: &str) -> u64 {
+            self.values.lock().unwrap()[name].load(Ordering::SeqCst)
+        }
+
+        fn gauge(&self, name: &str) -> f64 {
+            f64::from_bits(self.get(name))
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata) -> Counter {
+            Counter::from_arc(self.value(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata) -> Gauge {
+            Gauge::from_arc(self.value(key))
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata) -> Histogram {
+            assert_eq!(key.name(), "bb8.checkout_duration");
+            Histogram::from_arc(self.samples.clone())
+        }
+    }
+
+    let recorder = TestRecorder::default();
+    let mut event_loop = Runtime::new().unwrap();
+    let pool = metrics::with_local_recorder(&recorder, || {
+        event_loop
+            .block_on(lazy(|| {
+                Pool::builder()
+                    .name("primary")
+                    .max_size(2)
+                    .build(NthConnectionFailManager::<FakeConnection>::new(1))
+            }))
+            .unwrap()
+    });
+
+    let conn = event_loop.block_on(pool.get()).unwrap();
+    assert_eq!(recorder.get("bb8.connects"), 1);
+    assert_eq!(recorder.samples.0.lock().unwrap().len(), 1);
+    assert_eq!(recorder.gauge("bb8.connections"), 1.0);
+    assert_eq!(recorder.gauge("bb8.idle"), 0.0);
+
+    // Opening a second connection fails.
+    assert!(event_loop.block_on(pool.get()).is_err());
+    assert_eq!(recorder.get("bb8.connect_errors"), 1);
+
+    assert!(event_loop
+        .block_on(pool.get_timeout(Duration::from_secs(0)))
+        .unwrap_err()
+        .is_timeout());
+    assert_eq!(recorder.get("bb8.timeouts"), 1);
+    assert_eq!(recorder.gauge("bb8.waiters"), 0.0);
+
+    mem::drop(conn);
+    assert_eq!(recorder.gauge("bb8.connections"), 1.0);
+    assert_eq!(recorder.gauge("bb8.idle"), 1.0);
 }